@@ -0,0 +1,49 @@
+// Compares the recursive evaluation of an expression with the compiled RegexSet evaluation.
+//
+// The crate is a binary, so the expression modules are included directly.
+
+#[allow(dead_code, unused_imports)]
+#[path = "../src/expression_parser.rs"]
+mod expression_parser;
+#[allow(dead_code, unused_imports)]
+#[path = "../src/expression_interpreter.rs"]
+mod expression_interpreter;
+#[allow(dead_code, unused_imports)]
+#[path = "../src/expression_optimiser.rs"]
+mod expression_optimiser;
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use expression_interpreter::evaluate;
+use expression_optimiser::CompiledExprSet;
+
+// A test-detection pattern with many alternatives, as used on Java repositories
+const PATTERN: &str = "AND(OR(@Test,@ParameterizedTest,@RepeatedTest,@TestFactory,@TestTemplate,\
+    OR(import org\\.junit,import org\\.testng,import static org\\.junit\\.Assert),\
+    OR(extends TestCase,extends AbstractTestCase,extends TestBase,@RunWith,@ExtendWith)),\
+    NOT(OR(@Disabled,@Ignore,NOT(NOT(abstract class)))))";
+
+fn content(n_lines: usize) -> String {
+    let mut content = String::from("package com.example;\n\nimport java.util.List;\n\n");
+    content.push_str("public class ExampleService {\n");
+    for i in 0..n_lines {
+        content.push_str(&format!("    private final List<String> field{} = List.of(\"value\");\n", i));
+    }
+    content.push_str("}\n");
+    content
+}
+
+fn bench_evaluation(c: &mut Criterion) {
+    let expr = expression_parser::parse_many(&[PATTERN]).unwrap().pop().unwrap();
+    let compiled = CompiledExprSet::compile(&[&expr]).unwrap();
+
+    for n_lines in [100, 2000] {
+        let content = content(n_lines);
+        let mut group = c.benchmark_group(format!("evaluate_{}_lines", n_lines));
+        group.bench_function("recursive", |b| b.iter(|| evaluate(black_box(&expr), black_box(&content))));
+        group.bench_function("compiled", |b| b.iter(|| compiled.evaluate(black_box(&content))[0]));
+        group.finish();
+    }
+}
+
+criterion_group!(benches, bench_evaluation);
+criterion_main!(benches);
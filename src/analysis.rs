@@ -1,105 +1,580 @@
-use chrono::NaiveDateTime;
+use chrono::DateTime;
 use git2::{Commit, Repository};
 use crate::expression_parser::Expr;
 use color_eyre::eyre::Result;
-use std::collections::HashSet;
-use crate::commits::get_modified_files;
-use crate::expression_interpreter::evaluate;
-use crate::serialization::{CommitData, NativeDateTimeWrapper};
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::sync::Arc;
+use crate::blob_cache::{BlobCache, ContentMatches};
+use crate::commits::{get_modified_files, MatchTarget};
+use crate::decoding::{decode, is_binary, prefix, Encoding, SkippedFiles};
+use crate::detection::detect;
+use crate::expression_interpreter::{evaluate, explain};
+use crate::expression_optimiser::CompiledExprSet;
+use crate::commits::CommitFile;
+use crate::coevolution::CommitChanges;
+use crate::identities::{load_mailmap, resolve_identity, MailmapEntry};
+use crate::pairing::{imported_paths, is_pair};
+use crate::test_cases::{diff_test_cases, extract_test_cases, TestCase};
+use crate::snapshots::Snapshot;
+use crate::serialization::{CommitData, FileDetails, FileEntry, FileExplanation, NativeDateTimeWrapper};
 
-pub struct Analyser {
-    pub repo: Repository,
+pub struct Analyser<'r> {
+    pub repo: &'r Repository,
+    /// Object database of the repository, to read the sizes of the blobs from their headers
+    odb: git2::Odb<'r>,
     pub extensions: Vec<String>,
     pub expr: Expr,
+    compiled: CompiledExpressions,
+    skipped: RefCell<SkippedFiles>,
+    /// Identities of the .mailmap of the repository and of the extra mailmap
+    mailmap: git2::Mailmap,
     options: AnalyserOptions,
 }
 
+/// A named classification of the files, besides the test/non-test one. A file gets the label
+/// if its content matches the expression or its name matches the name expression.
+#[derive(Debug, Clone)]
+pub struct Classifier {
+    pub label: String,
+    pub expr: Option<Expr>,
+    pub name_expr: Option<Expr>,
+}
+
+#[derive(Debug, Clone)]
+struct CompiledClassifier {
+    label: String,
+    name_expr: Option<Expr>,
+}
+
+impl CompiledClassifier {
+    fn matches(&self, file_name: &str, content_matches: &ContentMatches) -> bool {
+        self.name_expr.as_ref().is_some_and(|expr| evaluate(expr, file_name))
+            || content_matches.labels.contains(&self.label)
+    }
+}
+
+// Classification of a blob, with its content if it was read
+struct ClassifiedBlob {
+    content_matches: ContentMatches,
+    content: Option<String>,
+}
+
+/// The content expressions, compiled once into a single set and cloned into each analyser,
+/// with the name expressions
+#[derive(Debug, Clone)]
+pub struct CompiledExpressions {
+    /// The expression, then the content expressions of the classifiers that have one
+    content: CompiledExprSet,
+    /// Labels of the classifiers with a content expression, in the order of the set
+    content_labels: Vec<String>,
+    name_expr: Option<Expr>,
+    classifiers: Vec<CompiledClassifier>,
+}
+
+impl CompiledExpressions {
+    pub fn compile(expr: &Expr, evaluate_name: Option<&Expr>, classifiers: &[Classifier]) -> Result<CompiledExpressions> {
+        let mut content_exprs = vec![expr];
+        let mut content_labels = Vec::new();
+        for classifier in classifiers {
+            if let Some(label_expr) = &classifier.expr {
+                content_exprs.push(label_expr);
+                content_labels.push(classifier.label.clone());
+            }
+        }
+
+        Ok(CompiledExpressions {
+            content: CompiledExprSet::compile(&content_exprs)?,
+            content_labels,
+            name_expr: evaluate_name.cloned(),
+            classifiers: classifiers.iter()
+                .map(|classifier| CompiledClassifier { label: classifier.label.clone(), name_expr: classifier.name_expr.clone() })
+                .collect(),
+        })
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct AnalyserOptions {
     pub(crate) evaluate_name: Option<Expr>,
     pub(crate) include_non_tests: bool,
+    /// Keep the commits with non-test files only, so the statistics count every commit
+    pub(crate) keep_non_test_commits: bool,
     pub(crate) full_path: bool,
+    pub(crate) match_target: MatchTarget,
+    pub(crate) explain: bool,
+    pub(crate) classifiers: Vec<Classifier>,
+    pub(crate) detect_frameworks: bool,
+    pub(crate) count_tests: bool,
+    pub(crate) track_tests: bool,
+    pub(crate) link_tests: bool,
+    pub(crate) encoding: Encoding,
+    pub(crate) max_blob_size: Option<u64>,
+    pub(crate) match_prefix: Option<usize>,
+    /// Classification of the blobs, shared between the analysers
+    pub(crate) blob_cache: Arc<BlobCache>,
+    /// Entries of the extra mailmap, taking precedence over the .mailmap of the repository
+    pub(crate) mailmap_entries: Vec<MailmapEntry>,
 }
 
-impl Analyser {
-    pub fn new(repo: Repository, extensions: Vec<String>, expr: Expr, opts: AnalyserOptions) -> Analyser {
-        Analyser {
+impl<'r> Analyser<'r> {
+    /// Creates an analyser with the expressions compiled from expr and the options, so each
+    /// file is scanned only once
+    pub fn new(repo: &'r Repository, extensions: Vec<String>, expr: Expr, compiled: CompiledExpressions, opts: AnalyserOptions) -> Result<Analyser<'r>> {
+        let mailmap = load_mailmap(repo, &opts.mailmap_entries)?;
+
+        Ok(Analyser {
             repo,
+            odb: repo.odb()?,
             extensions,
             expr,
+            compiled,
+            skipped: RefCell::new(SkippedFiles::default()),
+            mailmap,
             options: opts,
-        }
+        })
     }
 
     pub fn process_commit(&self, commit: &Commit) -> Result<Option<CommitData>> {
         let commit_id = commit.id();
-        let commit_date_time = NaiveDateTime::from_timestamp_opt(commit.time().seconds(), 0).unwrap();
+        let commit_date_time = DateTime::from_timestamp(commit.time().seconds(), 0).unwrap().naive_utc();
 
         let mut files = Vec::new();
         let mut non_test_files = Vec::new();
+        let mut tracked_test_files = Vec::new();
+        let mut explanations = Vec::new();
+        let mut total_test_cases = 0;
+        let mut production_candidates = Vec::new();
+        let mut test_imports = Vec::new();
+        let mut labels: BTreeMap<String, Vec<String>> = BTreeMap::new();
 
         // Get modified files from the commit
-        let (modified_files, changed_lines) = get_modified_files(&self.repo, commit, self.options.full_path)?;
+        let (modified_files, changed_lines) = get_modified_files(self.repo, commit, self.options.full_path, self.options.match_target, self.options.max_blob_size)?;
 
         for file in modified_files {
-            // Only consider new files
-            if file.modification_type != git2::Delta::Added {
-                continue;
-            }
-
-            // Get the referenced blob object
-            let blob = self.repo.find_blob(file.oid);
-            if blob.is_err() {
+            // Only list the files with content for the match target. The test cases are tracked
+            // in the test files changed in any way, which are listed separately
+            let listed = self.options.match_target.accepts(file.modification_type);
+            if !listed && !self.options.track_tests {
+                // A production file changed in any way can still be paired with the test files
+                if self.options.link_tests && self.is_production_blob(&file.name, file.oid) {
+                    production_candidates.push(file.name.clone());
+                }
                 continue;
             }
 
-            let blob = blob.unwrap();
+            // A deleted file is classified by its content before the commit
+            let content_oid = if file.oid.is_zero() { file.old_oid } else { file.oid };
+            let classified = match &file.diff_lines {
+                // The diff lines are matched instead of the blob, they are not cached
+                Some(diff_lines) => {
+                    if !self.has_extension(&file.name) || !self.check_blob_size(content_oid) {
+                        continue;
+                    }
+                    let Some(diff_content) = self.decode_counted(diff_lines) else {
+                        continue;
+                    };
+                    ClassifiedBlob {
+                        content_matches: self.match_content(self.matched_content(&diff_content), None),
+                        content: Some(diff_content.into_owned()),
+                    }
+                }
+                // The content is only read if the explanations or the file details need it
+                None => {
+                    let needs_content = self.options.explain || self.has_file_details();
+                    match self.classify_blob(&file.name, content_oid, |_| needs_content) {
+                        Some(classified) => classified,
+                        None => continue,
+                    }
+                }
+            };
+            let file_content = classified.content.as_deref().unwrap_or_default();
+            let content_matches = classified.content_matches;
 
-            // Get the file extension
-            let extension = std::path::Path::new(&file.name).extension();
+            // Include the file if it matches the expression
+            let is_test = self.is_test(&file.name, &content_matches);
 
-            // If it has an extension check it against the list of extensions
-            if let Some(extension) = extension {
-                let extension = extension.to_os_string().into_string().unwrap();
-                if self.extensions.contains(&extension) {
-                    // Read the blob content as utf8
-                    let file_content = std::str::from_utf8(blob.content());
-                    if file_content.is_err() {
-                        continue;
+            // Label the listed file with every classifier that matches it
+            if listed {
+                for classifier in &self.compiled.classifiers {
+                    if classifier.matches(&file.name, &content_matches) {
+                        labels.entry(classifier.label.clone()).or_default().push(file.name.clone());
                     }
+                }
+            }
+
+            // Any production file left by the commit can be paired with its test files
+            if !is_test && self.options.link_tests && !file.oid.is_zero() {
+                production_candidates.push(file.name.clone());
+            }
+
+            if !(is_test || self.options.include_non_tests && listed) {
+                continue;
+            }
 
-                    let file_content = file_content.unwrap();
+            // Record why the file was classified as it was
+            if self.options.explain && listed {
+                explanations.push(FileExplanation {
+                    file: file.name.clone(),
+                    content: Some(explain(&self.expr, self.matched_content(file_content))),
+                    name: self.options.evaluate_name.as_ref().map(|expr| explain(expr, &file.name)),
+                });
+            }
 
-                    // Include the file if it matches the expression
-                    let mut is_test = false;
+            // Detect the properties of the file, from the whole file if only the diff was matched
+            let mut entry = FileEntry::new(file.name.clone());
+            if self.has_file_details() {
+                // A deleted file has no content after the commit, so all its test cases
+                // are removed
+                let blob = file.diff_lines.as_ref()
+                    .filter(|_| !file.oid.is_zero())
+                    .and_then(|_| self.repo.find_blob(file.oid).ok());
+                let blob_content = blob.as_ref().and_then(|blob| decode(blob.content(), self.options.encoding));
+                let blob_content = match blob_content.as_deref() {
+                    Some(blob_content) => blob_content,
+                    None if file.oid.is_zero() => "",
+                    None => file_content,
+                };
+                let detection = detect(&file.name, blob_content);
 
-                    if let Some(evaluate_name) = &self.options.evaluate_name {
-                        is_test = is_test || evaluate(evaluate_name, &file.name);
-                    }
+                // Test cases are only extracted from test files
+                let test_cases = detection.language
+                    .filter(|_| (self.options.count_tests || self.options.track_tests) && is_test)
+                    .and_then(|language| extract_test_cases(language, blob_content));
+                let test_count = test_cases.as_ref().map(|test_cases| test_cases.len());
+                if listed {
+                    total_test_cases += test_count.unwrap_or(0);
+                }
 
-                    is_test = is_test || evaluate(&self.expr, file_content);
+                // Compare with the test cases before the commit
+                let test_changes = test_cases.as_ref()
+                    .filter(|_| self.options.track_tests)
+                    .and_then(|test_cases| {
+                        let old_test_cases = self.old_test_cases(&file, detection.language.unwrap())?;
+                        Some(diff_test_cases(&old_test_cases, test_cases))
+                    });
 
-                    if is_test {
-                        files.push(file.name);
-                    } else if self.options.include_non_tests {
-                        non_test_files.push(file.name);
-                    }
+                // The imports of the test file are used to pair it with production files
+                if self.options.link_tests && is_test && listed {
+                    test_imports.push((files.len(), imported_paths(&file.name, blob_content)));
                 }
+
+                let detect_frameworks = self.options.detect_frameworks;
+                entry = FileEntry::with_details(file.name.clone(), FileDetails {
+                    language: detection.language.filter(|_| detect_frameworks).map(String::from),
+                    framework: detection.framework.filter(|_| detect_frameworks).map(String::from),
+                    test_cases: test_count.filter(|_| self.options.count_tests),
+                    test_names: test_cases.filter(|_| self.options.track_tests)
+                        .map(|test_cases| test_cases.into_iter().map(|test_case| test_case.name).collect()),
+                    test_changes,
+                    production_files: None,
+                    history_production_files: None,
+                });
             }
+
+            if !listed {
+                tracked_test_files.push(entry);
+            } else if is_test {
+                files.push(entry);
+            } else {
+                non_test_files.push(entry);
+            }
+        }
+
+        // Pair the test files with the production files changed in the same commit
+        for (idx, imported) in test_imports {
+            let entry = &mut files[idx];
+            entry.details.production_files = Some(production_candidates.iter()
+                .filter(|candidate| is_pair(&entry.file, candidate, &imported))
+                .cloned()
+                .collect());
         }
 
-        if !files.is_empty() {
+        let has_non_test_files = self.options.keep_non_test_commits && !non_test_files.is_empty();
+        if !files.is_empty() || !labels.is_empty() || !tracked_test_files.is_empty() || has_non_test_files {
             Ok(Some(CommitData {
                 commit: commit_id.to_string(),
                 date: NativeDateTimeWrapper(commit_date_time),
+                author: Some(resolve_identity(&self.mailmap, &commit.author())),
+                committer: Some(resolve_identity(&self.mailmap, &commit.committer())),
                 size: changed_lines,
                 test_files: files,
                 non_test_files: if self.options.include_non_tests { Some(non_test_files) } else { None },
+                tracked_test_files: self.options.track_tests.then_some(tracked_test_files),
+                explanations: if self.options.explain { Some(explanations) } else { None },
+                test_cases: if self.options.count_tests { Some(total_test_cases) } else { None },
+                labels: if self.options.classifiers.is_empty() { None } else { Some(labels) },
             }))
         } else {
             Ok(None)
         }
     }
+
+    /// Classifies every added or modified file of the commit with one of the extensions as a
+    /// test or production file, evaluating the expressions against the whole file
+    pub fn classify_changes(&self, commit: &Commit, position: usize) -> Result<CommitChanges> {
+        let mut changes = CommitChanges {
+            commit: commit.id().to_string(),
+            position,
+            author: resolve_identity(&self.mailmap, &commit.author()),
+            date: DateTime::from_timestamp(commit.time().seconds(), 0).unwrap().naive_utc(),
+            test_files: Vec::new(),
+            production_files: Vec::new(),
+            test_imports: Vec::new(),
+        };
+
+        let (modified_files, _) = get_modified_files(self.repo, commit, self.options.full_path, MatchTarget::Blob, self.options.max_blob_size)?;
+
+        for file in modified_files {
+            // Renames are not detected, a renamed file is deleted and added
+            if !matches!(file.modification_type, git2::Delta::Added | git2::Delta::Modified) {
+                continue;
+            }
+
+            // The content is only read for the test files, for their imports
+            let Some(classified) = self.classify_blob(&file.name, file.oid, |content_matches| self.is_test(&file.name, content_matches)) else {
+                continue;
+            };
+            let content_matches = classified.content_matches;
+
+            if self.is_test(&file.name, &content_matches) {
+                changes.test_imports.push(imported_paths(&file.name, classified.content.as_deref().unwrap_or_default()));
+                changes.test_files.push(file.name);
+            } else {
+                changes.production_files.push(file.name);
+            }
+        }
+
+        Ok(changes)
+    }
+
+    /// Classifies every file of the tree of the commit with one of the extensions, as
+    /// process_commit does with the changed files
+    pub fn process_tree(&self, commit: &Commit, revision: &str) -> Result<Snapshot> {
+        // Every blob of the tree, named as the changed files are
+        let mut tree_files = Vec::new();
+        commit.tree()?.walk(git2::TreeWalkMode::PreOrder, |root, entry| {
+            if entry.kind() == Some(git2::ObjectType::Blob) {
+                let name = entry.name().unwrap_or_default();
+                let name = if self.options.full_path { format!("{}{}", root, name) } else { name.to_string() };
+                tree_files.push((name, entry.id()));
+            }
+            git2::TreeWalkResult::Ok
+        })?;
+
+        let mut snapshot = Snapshot {
+            revision: revision.to_string(),
+            commit: commit.id().to_string(),
+            date: NativeDateTimeWrapper(DateTime::from_timestamp(commit.time().seconds(), 0).unwrap().naive_utc()),
+            files: 0,
+            test_files: Vec::new(),
+            non_test_files: self.options.include_non_tests.then(Vec::new),
+            test_cases: self.options.count_tests.then_some(0),
+            labels: (!self.options.classifiers.is_empty()).then(BTreeMap::new),
+        };
+        let has_file_details = self.options.detect_frameworks || self.options.count_tests;
+
+        for (name, oid) in tree_files {
+            // The content is only read if the file details need it
+            let Some(classified) = self.classify_blob(&name, oid, |_| has_file_details) else {
+                continue;
+            };
+            let file_content = classified.content.unwrap_or_default();
+            let content_matches = classified.content_matches;
+            let is_test = self.is_test(&name, &content_matches);
+            snapshot.files += 1;
+
+            if let Some(labels) = &mut snapshot.labels {
+                for classifier in &self.compiled.classifiers {
+                    if classifier.matches(&name, &content_matches) {
+                        labels.entry(classifier.label.clone()).or_default().push(name.clone());
+                    }
+                }
+            }
+
+            if !is_test && !self.options.include_non_tests {
+                continue;
+            }
+
+            let entry = if has_file_details {
+                let detection = detect(&name, &file_content);
+                let test_count = detection.language
+                    .filter(|_| self.options.count_tests && is_test)
+                    .and_then(|language| extract_test_cases(language, &file_content))
+                    .map(|test_cases| test_cases.len());
+                if let Some(test_cases) = &mut snapshot.test_cases {
+                    *test_cases += test_count.unwrap_or(0);
+                }
+
+                let detect_frameworks = self.options.detect_frameworks;
+                FileEntry::with_details(name, FileDetails {
+                    language: detection.language.filter(|_| detect_frameworks).map(String::from),
+                    framework: detection.framework.filter(|_| detect_frameworks).map(String::from),
+                    test_cases: test_count,
+                    ..Default::default()
+                })
+            } else {
+                FileEntry::new(name)
+            };
+
+            match (is_test, &mut snapshot.non_test_files) {
+                (true, _) => snapshot.test_files.push(entry),
+                (false, Some(non_test_files)) => non_test_files.push(entry),
+                (false, None) => {}
+            }
+        }
+
+        Ok(snapshot)
+    }
+
+    /// Files skipped by this analyser so far, by reason
+    pub fn skipped_files(&self) -> SkippedFiles {
+        self.skipped.borrow().clone()
+    }
+
+    // Whether the file has one of the extensions and its blob is not a test, reading the blob
+    // only if it was not classified before
+    fn is_production_blob(&self, file_name: &str, oid: git2::Oid) -> bool {
+        if oid.is_zero() {
+            return false;
+        }
+
+        self.classify_blob(file_name, oid, |_| false)
+            .is_some_and(|classified| !self.is_test(file_name, &classified.content_matches))
+    }
+
+    // Classifies a blob of a file with one of the extensions: skips it if it is too large,
+    // reuses its classification if it is in the blob cache, or reads it and evaluates the
+    // content expressions. A cached blob is only read if needs_content asks for its content.
+    // None if the file is not classified, a skipped blob is counted as such
+    fn classify_blob<F>(&self, file_name: &str, oid: git2::Oid, needs_content: F) -> Option<ClassifiedBlob>
+    where
+        F: FnOnce(&ContentMatches) -> bool,
+    {
+        if !self.has_extension(file_name) || !self.check_blob_size(oid) {
+            return None;
+        }
+
+        let cached = self.options.blob_cache.get(oid);
+        if let Some(content_matches) = cached.as_ref().filter(|cached| !needs_content(cached)) {
+            return Some(ClassifiedBlob { content_matches: content_matches.clone(), content: None });
+        }
+
+        let Ok(blob) = self.repo.find_blob(oid) else {
+            self.skipped.borrow_mut().missing += 1;
+            return None;
+        };
+        let content = self.read_blob(&blob)?;
+        let content_matches = cached.unwrap_or_else(|| self.match_content(self.matched_content(&content), Some(oid)));
+        Some(ClassifiedBlob { content_matches, content: Some(content.into_owned()) })
+    }
+
+    // Whether the blob exists and is within the maximum size, reading only its header.
+    // Counts it as skipped otherwise.
+    fn check_blob_size(&self, oid: git2::Oid) -> bool {
+        let Some(max_blob_size) = self.options.max_blob_size else {
+            return true;
+        };
+
+        match self.odb.read_header(oid) {
+            Ok((size, _)) if size as u64 > max_blob_size => {
+                self.skipped.borrow_mut().too_large += 1;
+                false
+            }
+            Ok(_) => true,
+            Err(_) => {
+                self.skipped.borrow_mut().missing += 1;
+                false
+            }
+        }
+    }
+
+    // The part of the content the expressions are evaluated against
+    fn matched_content<'c>(&self, content: &'c str) -> &'c str {
+        match self.options.match_prefix {
+            Some(match_prefix) => prefix(content, match_prefix * 1024),
+            None => content,
+        }
+    }
+
+    // Decodes the content of a blob, counting it as skipped if it is binary or undecodable
+    fn read_blob<'b>(&self, blob: &'b git2::Blob) -> Option<Cow<'b, str>> {
+        if is_binary(blob, self.options.encoding) {
+            self.skipped.borrow_mut().binary += 1;
+            return None;
+        }
+
+        self.decode_counted(blob.content())
+    }
+
+    fn decode_counted<'b>(&self, content: &'b [u8]) -> Option<Cow<'b, str>> {
+        let decoded = decode(content, self.options.encoding);
+        if decoded.is_none() {
+            self.skipped.borrow_mut().undecodable += 1;
+        }
+        decoded
+    }
+
+    // Evaluates the content expressions, caching the results by the oid of the blob if the
+    // content is a whole blob
+    fn match_content(&self, file_content: &str, oid: Option<git2::Oid>) -> ContentMatches {
+        // The expression and the content expressions of the classifiers, in a single pass
+        let mut results = self.compiled.content.evaluate(file_content).into_iter();
+        let content_matches = ContentMatches {
+            is_test: results.next().unwrap_or_default(),
+            labels: self.compiled.content_labels.iter()
+                .zip(results)
+                .filter(|(_, matched)| *matched)
+                .map(|(label, _)| label.clone())
+                .collect(),
+        };
+
+        if let Some(oid) = oid {
+            self.options.blob_cache.insert(oid, content_matches.clone());
+        }
+        content_matches
+    }
+
+    // A file is a test if its name matches the name expression or its content the expression
+    fn is_test(&self, file_name: &str, content_matches: &ContentMatches) -> bool {
+        if let Some(evaluate_name) = &self.compiled.name_expr {
+            if evaluate(evaluate_name, file_name) {
+                return true;
+            }
+        }
+
+        content_matches.is_test
+    }
+
+    fn has_extension(&self, file_name: &str) -> bool {
+        std::path::Path::new(file_name).extension()
+            .and_then(|extension| extension.to_str())
+            .is_some_and(|extension| self.extensions.iter().any(|e| e == extension))
+    }
+
+    fn has_file_details(&self) -> bool {
+        self.options.detect_frameworks || self.options.count_tests || self.options.track_tests || self.options.link_tests
+    }
+
+    // Returns the test cases of the file before the commit, none for added files. None when
+    // the file was too large before the commit, it is counted as skipped
+    fn old_test_cases(&self, file: &CommitFile, language: &str) -> Option<Vec<TestCase>> {
+        if file.old_oid.is_zero() {
+            return Some(Vec::new());
+        }
+
+        // None if the old version is too large to be read
+        if !self.check_blob_size(file.old_oid) {
+            return None;
+        }
+
+        let test_cases = self.repo.find_blob(file.old_oid).ok()
+            .and_then(|blob| decode(blob.content(), self.options.encoding).map(String::from))
+            .and_then(|content| extract_test_cases(language, &content))
+            .unwrap_or_default();
+        Some(test_cases)
+    }
 }
 
 // Given a sorted Vec<CommitData>, remove files names, not taking into account the path, that are
@@ -109,40 +584,298 @@ pub fn delete_duplicates(commit_data: &[CommitData]) -> Vec<CommitData> {
     let mut result = Vec::new();
 
     for commit in commit_data.iter() {
-        let mut files: Vec<Vec<String>> = vec![Vec::new();2];
-
-        let mut handles = vec![&commit.test_files];
-
-        if let Some(non_test_files) = &commit.non_test_files {
-            handles.push(non_test_files);
-        }
+        // Files kept in this commit, so a file is kept under every label it has
+        let mut kept_files = HashSet::new();
 
-        for (i, handle) in handles.into_iter().enumerate() {
-            for file in handle.iter() {
-                let path= std::path::Path::new(file.as_str());
-                let file_name = path.file_name().unwrap().to_str().unwrap();
+        let test_files = keep_unique(&commit.test_files, |entry| &entry.file, &mut seen_files, &mut kept_files);
+        let non_test_files = commit.non_test_files.as_ref()
+            .map(|files| keep_unique(files, |entry| &entry.file, &mut seen_files, &mut kept_files))
+            .unwrap_or_default();
+        let labels = commit.labels.as_ref().map(|labels| {
+            labels.iter()
+                .map(|(label, files)| (label.clone(), keep_unique(files, |file| file, &mut seen_files, &mut kept_files)))
+                .filter(|(_, files)| !files.is_empty())
+                .collect::<BTreeMap<String, Vec<String>>>()
+        });
 
-                if !seen_files.contains(file_name) {
-                    files[i].push(file.clone());
-                    seen_files.insert(file_name);
-                }
-            }
-        }
+        let has_labels = labels.as_ref().is_some_and(|labels| !labels.is_empty());
+        // The tracked test files are kept as they are, they only record the test changes
+        let has_tracked = commit.tracked_test_files.as_ref().is_some_and(|files| !files.is_empty());
 
-        let mut files = files.into_iter();
-        let test_files = files.next().unwrap();
-        let non_test_files = files.next().unwrap_or_default();
+        if !test_files.is_empty() || !non_test_files.is_empty() || has_labels || has_tracked {
+            // Keep only the explanations of the remaining files
+            let explanations = commit.explanations.as_ref().map(|explanations| {
+                explanations.iter()
+                    .filter(|explanation| kept_files.contains(explanation.file.as_str()))
+                    .cloned()
+                    .collect()
+            });
+            let test_cases = commit.test_cases.map(|_| {
+                test_files.iter().filter_map(|entry| entry.details.test_cases).sum()
+            });
 
-        if !test_files.is_empty() || !non_test_files.is_empty() {
             result.push(CommitData {
                 commit: commit.commit.clone(),
                 size: commit.size,
                 date: commit.date.clone(),
+                author: commit.author.clone(),
+                committer: commit.committer.clone(),
                 test_files,
                 non_test_files: if non_test_files.is_empty() { None } else { Some(non_test_files) },
+                tracked_test_files: commit.tracked_test_files.clone(),
+                explanations,
+                test_cases,
+                labels,
             });
         }
     }
 
     result
 }
+
+// Pairs the test files with the production files recorded anywhere in the analysed history,
+// by name only, as the content of the test files is no longer available.
+pub fn link_history(commit_data: &mut [CommitData]) {
+    let production_files: BTreeSet<String> = commit_data.iter()
+        .flat_map(|commit| commit.non_test_files.iter().flatten())
+        .map(|entry| entry.file.clone())
+        .collect();
+
+    for commit in commit_data.iter_mut() {
+        for entry in commit.test_files.iter_mut() {
+            entry.details.history_production_files = Some(production_files.iter()
+                .filter(|production_file| is_pair(&entry.file, production_file, &[]))
+                .cloned()
+                .collect());
+        }
+    }
+}
+
+// Returns the files whose names have not been seen before, or that were already kept in
+// the current commit, marking them as seen
+fn keep_unique<'a, T: Clone>(handle: &'a [T], name: impl Fn(&'a T) -> &'a String, seen_files: &mut HashSet<&'a str>, kept_files: &mut HashSet<&'a str>) -> Vec<T> {
+    let mut files = Vec::new();
+
+    for file in handle.iter() {
+        let path_name = name(file);
+        let path= std::path::Path::new(path_name.as_str());
+        let file_name = path.file_name().unwrap().to_str().unwrap();
+
+        if !seen_files.contains(file_name) {
+            files.push(file.clone());
+            seen_files.insert(file_name);
+            kept_files.insert(path_name.as_str());
+        } else if kept_files.contains(path_name.as_str()) {
+            files.push(file.clone());
+        }
+    }
+
+    files
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+    use crate::expression_parser::parse;
+    use super::*;
+
+    // A repository in a new temporary directory
+    fn init_repository(name: &str) -> Repository {
+        let path = std::env::temp_dir().join(format!("analysis_{}_{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+        Repository::init(&path).unwrap()
+    }
+
+    // Writes the files, removing the ones without content, and commits them
+    fn commit_files<'r>(repo: &'r Repository, files: &[(&str, Option<&str>)], message: &str) -> Commit<'r> {
+        let workdir = repo.workdir().unwrap();
+        let mut index = repo.index().unwrap();
+        for (file, content) in files {
+            let path = std::path::Path::new(file);
+            match content {
+                Some(content) => {
+                    std::fs::create_dir_all(workdir.join(path).parent().unwrap()).unwrap();
+                    std::fs::write(workdir.join(path), content).unwrap();
+                    index.add_path(path).unwrap();
+                }
+                None => {
+                    std::fs::remove_file(workdir.join(path)).unwrap();
+                    index.remove_path(path).unwrap();
+                }
+            }
+        }
+        index.write().unwrap();
+
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let signature = git2::Signature::now("Jane", "jane@example.com").unwrap();
+        let parent = repo.head().ok().map(|head| head.peel_to_commit().unwrap());
+        let oid = repo.commit(Some("HEAD"), &signature, &signature, message, &tree, parent.as_ref().into_iter().collect::<Vec<_>>().as_slice()).unwrap();
+        repo.find_commit(oid).unwrap()
+    }
+
+    fn options() -> AnalyserOptions {
+        AnalyserOptions {
+            evaluate_name: None,
+            include_non_tests: false,
+            keep_non_test_commits: false,
+            full_path: true,
+            match_target: MatchTarget::Blob,
+            explain: false,
+            classifiers: Vec::new(),
+            detect_frameworks: false,
+            count_tests: false,
+            track_tests: false,
+            link_tests: false,
+            encoding: Encoding::Strict,
+            max_blob_size: None,
+            match_prefix: None,
+            blob_cache: Arc::new(BlobCache::new(String::new())),
+            mailmap_entries: Vec::new(),
+        }
+    }
+
+    fn analyser(repo: &Repository, opts: AnalyserOptions) -> Analyser<'_> {
+        let expr = parse("@Test").unwrap();
+        let name_expr = opts.evaluate_name.clone();
+        let compiled = CompiledExpressions::compile(&expr, name_expr.as_ref(), &opts.classifiers).unwrap();
+        Analyser::new(repo, vec!["java".to_string()], expr, compiled, opts).unwrap()
+    }
+
+    const FOO_TEST: &str = "class FooTest {\n    @Test\n    void first() {}\n\n    @Test\n    void second() {}\n}\n";
+
+    #[test]
+    fn test_track_deleted_test_file() {
+        let repo = init_repository("track_deleted");
+        commit_files(&repo, &[("src/FooTest.java", Some(FOO_TEST))], "add");
+        let deletion = commit_files(&repo, &[("src/FooTest.java", None)], "delete");
+
+        // The deleted file is only listed as a test file when the removed lines are matched
+        for (match_target, listed) in [(MatchTarget::Blob, false), (MatchTarget::RemovedLines, true)] {
+            let analyser = analyser(&repo, AnalyserOptions { track_tests: true, match_target, ..options() });
+            let commit_data = analyser.process_commit(&deletion).unwrap().unwrap();
+            let tracked_test_files = commit_data.tracked_test_files.unwrap();
+
+            let (entry, other) = if listed {
+                (&commit_data.test_files, &tracked_test_files)
+            } else {
+                (&tracked_test_files, &commit_data.test_files)
+            };
+            assert!(other.is_empty(), "{:?}", match_target);
+            let details = &entry[0].details;
+            assert_eq!(details.test_names, Some(Vec::new()), "{:?}", match_target);
+            assert_eq!(details.test_changes.as_ref().unwrap().removed, vec!["first", "second"], "{:?}", match_target);
+        }
+
+        std::fs::remove_dir_all(repo.workdir().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_keep_non_test_commits() {
+        let repo = init_repository("keep_non_test");
+        commit_files(&repo, &[("src/FooTest.java", Some(FOO_TEST))], "test");
+        let production = commit_files(&repo, &[("src/Foo.java", Some("class Foo {}\n"))], "production");
+        let other = commit_files(&repo, &[("README.md", Some("Foo\n"))], "other");
+
+        // The commits without test files are only kept for the statistics
+        let analyser_with = |keep_non_test_commits| analyser(&repo, AnalyserOptions { include_non_tests: true, keep_non_test_commits, ..options() });
+        assert!(analyser_with(false).process_commit(&production).unwrap().is_none());
+        let commit_data = analyser_with(true).process_commit(&production).unwrap().unwrap();
+        assert!(commit_data.test_files.is_empty());
+        assert_eq!(commit_data.non_test_files.unwrap().iter().map(|entry| entry.file.as_str()).collect::<Vec<_>>(), vec!["src/Foo.java"]);
+
+        // A commit without a file of the extensions has nothing to count
+        assert!(analyser_with(true).process_commit(&other).unwrap().is_none());
+
+        std::fs::remove_dir_all(repo.workdir().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_process_tree_at_tag() {
+        let repo = init_repository("process_tree");
+        let tagged = commit_files(&repo, &[
+            ("src/test/FooTest.java", Some(FOO_TEST)),
+            ("src/main/Foo.java", Some("class Foo {}\n")),
+            ("README.md", Some("@Test\n")),
+        ], "first release");
+        repo.tag_lightweight("v1", tagged.as_object(), false).unwrap();
+        commit_files(&repo, &[("src/test/BarTest.java", Some(FOO_TEST))], "after the release");
+
+        let classifiers = vec![Classifier { label: "main".to_string(), expr: None, name_expr: Some(parse("src/main").unwrap()) }];
+        let analyser = analyser(&repo, AnalyserOptions { include_non_tests: true, count_tests: true, classifiers, ..options() });
+        let commit = repo.revparse_single("v1").unwrap().peel_to_commit().unwrap();
+        let snapshot = analyser.process_tree(&commit, "v1").unwrap();
+
+        // Only the files of the tag with one of the extensions are classified
+        assert_eq!((snapshot.revision.as_str(), snapshot.commit.as_str()), ("v1", tagged.id().to_string().as_str()));
+        assert_eq!(snapshot.files, 2);
+        assert_eq!(snapshot.test_files.iter().map(|entry| entry.file.as_str()).collect::<Vec<_>>(), vec!["src/test/FooTest.java"]);
+        assert_eq!(snapshot.test_files[0].details.test_cases, Some(2));
+        assert_eq!(snapshot.non_test_files.unwrap().iter().map(|entry| entry.file.as_str()).collect::<Vec<_>>(), vec!["src/main/Foo.java"]);
+        assert_eq!(snapshot.test_cases, Some(2));
+        assert_eq!(snapshot.labels.unwrap().get("main"), Some(&vec!["src/main/Foo.java".to_string()]));
+
+        std::fs::remove_dir_all(repo.workdir().unwrap()).unwrap();
+    }
+
+    fn commit_data(commit: &str, test_files: &[&str], labels: &[(&str, &[&str])]) -> CommitData {
+        CommitData {
+            date: NativeDateTimeWrapper(NaiveDate::from_ymd_opt(2023, 1, 1).unwrap().and_hms_opt(0, 0, 0).unwrap()),
+            labels: Some(labels.iter()
+                .map(|(label, files)| (label.to_string(), files.iter().map(|file| file.to_string()).collect()))
+                .collect()),
+            ..CommitData::with_test_files(commit, test_files)
+        }
+    }
+
+    #[test]
+    fn test_link_modified_production_files() {
+        let repo = init_repository("link_modified");
+        commit_files(&repo, &[
+            ("src/main/com/example/Foo.java", Some("package com.example;\nclass Foo {}\n")),
+            ("src/main/com/example/Bar.java", Some("package com.example;\nclass Bar {}\n")),
+            ("src/main/com/other/Baz.java", Some("package com.other;\nclass Baz {}\n")),
+        ], "add");
+        let commit = commit_files(&repo, &[
+            ("src/test/com/example/FooTest.java", Some("package com.example;\nclass FooTest {\n    @Test\n    void first() { new Bar(); }\n}\n")),
+            ("src/main/com/example/Foo.java", Some("package com.example;\nclass Foo { int a; }\n")),
+            ("src/main/com/example/Bar.java", Some("package com.example;\nclass Bar { int b; }\n")),
+            ("src/main/com/other/Baz.java", Some("package com.other;\nclass Baz { int c; }\n")),
+        ], "test");
+
+        let analyser = analyser(&repo, AnalyserOptions { link_tests: true, ..options() });
+        let commit_data = analyser.process_commit(&commit).unwrap().unwrap();
+        let mut production_files = commit_data.test_files[0].details.production_files.clone().unwrap();
+        production_files.sort();
+        assert_eq!(production_files, vec!["src/main/com/example/Bar.java", "src/main/com/example/Foo.java"]);
+
+        std::fs::remove_dir_all(repo.workdir().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_link_history() {
+        let mut first = commit_data("a", &[], &[]);
+        first.non_test_files = Some(vec![FileEntry::new("src/Foo.java".to_string()), FileEntry::new("src/Bar.java".to_string())]);
+        let mut second = commit_data("b", &["test/FooTest.java"], &[]);
+        second.non_test_files = Some(vec![FileEntry::new("src/Foo.java".to_string())]);
+
+        let mut commits = vec![first, second];
+        link_history(&mut commits);
+        assert_eq!(commits[1].test_files[0].details.history_production_files, Some(vec!["src/Foo.java".to_string()]));
+    }
+
+    #[test]
+    fn test_delete_duplicates_labels() {
+        let commits = vec![
+            commit_data("a", &["src/FooTest.java"], &[("unit", &["src/FooTest.java"])]),
+            commit_data("b", &["other/FooTest.java"], &[("unit", &["other/FooTest.java"]), ("fixture", &["Data.java"])]),
+        ];
+
+        let result = delete_duplicates(&commits);
+        assert_eq!(result.len(), 2);
+        assert_eq!(result[0].labels.as_ref().unwrap()["unit"], vec!["src/FooTest.java"]);
+        assert!(result[1].test_files.is_empty());
+        let labels = result[1].labels.as_ref().unwrap();
+        assert!(!labels.contains_key("unit"));
+        assert_eq!(labels["fixture"], vec!["Data.java"]);
+    }
+}
@@ -1,10 +1,20 @@
-use clap::Parser;
+use clap::{Args, Parser, Subcommand};
 use chrono::prelude::*;
 use color_eyre::eyre::Result;
+use crate::commits::{HistoryOrder, MatchTarget};
+use crate::decoding::Encoding;
+use crate::periods::Period;
+use crate::serialization::OutputFormat;
+use crate::stats::StatsGroup;
+use serde::Serialize;
 
-#[derive(Parser)]
-#[command(author, version, about, long_about=None)]
+#[derive(Parser, Serialize)]
+#[command(author, version, about, long_about=None, subcommand_negates_reqs=true)]
 pub struct Arguments {
+    #[command(subcommand)]
+    #[serde(skip)]
+    pub command: Option<Command>,
+
     /// Path to the git repository
     #[clap(short, long, default_value=".")]
     pub path: String,
@@ -13,7 +23,8 @@ pub struct Arguments {
     #[clap(short, long, default_value="master")]
     pub branch: String,
 
-    /// Whether to append to the output file
+    /// Whether to append to the output file. Only valid when the commits are written with
+    /// --format jsonl or sqlite
     #[clap(short, long, default_value="false")]
     pub append: bool,
 
@@ -53,14 +64,186 @@ pub struct Arguments {
     #[clap(long, default_value="false")]
     pub full_path: bool,
 
-    /// Compare regex against the file name
+    /// Compare regex against the file name.
+    /// Can reference the named expressions defined in either expression
     #[clap(long)]
     pub evaluate_name: Option<String>,
 
+    /// Additional classifier, given as LABEL=EXPRESSION. The files whose content matches the
+    /// expression are listed under the label. Can be repeated.
+    #[clap(long, value_parser=parse_label)]
+    pub label: Vec<(String, String)>,
+
+    /// Name expression of a classifier, given as LABEL=EXPRESSION. The files whose name
+    /// matches the expression are listed under the label. Can be repeated.
+    #[clap(long, value_parser=parse_label)]
+    pub label_name: Vec<(String, String)>,
+
+    /// Detect the language and the likely test framework of each file, from its extension,
+    /// imports and annotations
+    #[clap(long, default_value="false")]
+    pub detect_frameworks: bool,
+
+    /// Count the test cases declared in each test file, such as @Test methods or def test_
+    /// functions, and the total for each commit
+    #[clap(long, default_value="false")]
+    pub count_tests: bool,
+
+    /// Record the names of the test cases in each test file, and the test cases added, removed
+    /// and renamed by the commit. The added, modified and deleted files are all tracked,
+    /// whatever the match target, and the test files outside of it are written under
+    /// tracked_test_files. A deleted file is classified by its content before the commit
+    #[clap(long, default_value="false")]
+    pub track_tests: bool,
+
+    /// Pair each test file with the production files changed in the same commit that it likely
+    /// exercises, by file name conventions (FooTest.java and Foo.java, test_foo.py and foo.py),
+    /// by the imports of the test file and by the classes it uses from its own package. The
+    /// production files added or modified are paired, whatever the match target
+    #[clap(long, default_value="false")]
+    pub link_tests: bool,
+
+    /// Also pair each test file with the production files recorded anywhere in the analysed
+    /// history, by file name conventions only
+    #[clap(long, default_value="false", requires_all=["link_tests", "save_non_tests"])]
+    pub link_tests_history: bool,
+
+    /// Write a test-to-code co-evolution report instead of the commits. For each production
+    /// file, every commit that changed it is classified by whether a paired test changed in the
+    /// same commit, within the given number of commits before or after, outside that window, or
+    /// never. Added and modified files are considered, the expressions are evaluated against
+    /// the whole file
+    #[clap(long, value_name="WINDOW")]
+    pub co_evolution: Option<usize>,
+
+    /// Write a TDD report instead of the commits. For each pair of test and production files,
+    /// the first commits touching each side are compared to tell whether the test was added
+    /// before, together with or after the production code, with the ratios by author and by
+    /// period
+    #[clap(long, default_value="false", conflicts_with="co_evolution")]
+    pub tdd_report: bool,
+
+    /// Print and write the counts of the matched commits for each group given by --stats-by:
+    /// commits, commits with tests, test files, non-test files and their ratio. The non-test
+    /// files are counted whether --save-non-tests is given or not.
+    /// Written as CSV with --format csv, as JSON otherwise
+    #[clap(long, default_value="false", conflicts_with_all=["co_evolution", "tdd_report"])]
+    pub stats: bool,
+
+    /// How the commits are grouped in the statistics.
+    ///
+    /// period - by the period of the commit date, see --period
+    ///
+    /// release - by the first release tag containing the commit
+    ///
+    /// author - by the author of the commit, with the identities merged by the .mailmap and
+    /// --mailmap
+    #[clap(long, value_enum, default_value="period", requires="stats")]
+    pub stats_by: StatsGroup,
+
+    /// Write the test inventory of a tag or commit instead of the commits: every file of its
+    /// tree with one of the extensions is classified. Can be given several times, to follow
+    /// the size of the test suite over the releases. Written as JSON, only the file details
+    /// of --detect-frameworks and --count-tests apply. The branch and the range of the history
+    /// are not given, the metadata records the HEAD of the repository
+    #[clap(long, value_name="REV", conflicts_with_all=["co_evolution", "tdd_report", "stats", "explain", "track_tests", "link_tests", "branch", "start_date", "end_date", "start_commit"])]
+    pub snapshot: Vec<String>,
+
+    /// Period used to group the results of the reports
+    #[clap(long, value_enum, default_value="month")]
+    pub period: Period,
+
+    /// Order in which the history is walked and compared
+    #[clap(long, value_enum, default_value="time")]
+    pub history_order: HistoryOrder,
+
+    /// How the files that are not valid UTF-8 are read.
+    /// Binary files are always skipped.
+    ///
+    /// strict - skip the files that are not valid UTF-8
+    ///
+    /// lossy - replace the invalid sequences
+    ///
+    /// detect - decode UTF-16 files with a byte order mark, and the other files that are not
+    /// valid UTF-8 as Latin-1
+    #[clap(long, value_enum, default_value="strict")]
+    pub encoding: Encoding,
+
+    /// Skip the files larger than this size in bytes.
+    /// The size is read from the object header, before the content.
+    #[clap(long, value_name="BYTES")]
+    pub max_blob_size: Option<u64>,
+
+    /// Only evaluate the expressions against the first KB kilobytes of each file
+    #[clap(long, value_name="KB")]
+    pub match_prefix: Option<usize>,
+
+    /// File where the classification of the blobs is kept between runs. The blobs classified
+    /// by a previous run with the same expressions are not scanned again
+    #[clap(long, value_name="FILE")]
+    pub blob_cache: Option<String>,
+
+    /// Extra mailmap file, in the format of git's .mailmap. Its entries take precedence over
+    /// the .mailmap of the repository when the authors and committers are resolved
+    #[clap(long, value_name="FILE")]
+    pub mailmap: Option<String>,
+
+    /// Directory where the results of each commit are kept between runs, for each
+    /// configuration. Only the commits missing from the cache are analysed. Not used by the
+    /// co-evolution and TDD reports
+    #[clap(long, value_name="DIR")]
+    pub cache_dir: Option<String>,
+
     /// Include non-test files in the analysis
     #[clap(long, default_value="false")]
     pub save_non_tests: bool,
 
+    /// Content the expression is matched against.
+    ///
+    /// blob - the full content of added files
+    ///
+    /// added-lines - the lines added by the commit to new or modified files
+    ///
+    /// removed-lines - the lines removed by the commit from modified or deleted files
+    #[clap(long, value_enum, default_value="blob")]
+    pub match_target: MatchTarget,
+
+    /// Include in the output the evaluation tree of the expressions for each file, with the
+    /// result of each sub-expression and the first match of each regular expression
+    #[clap(long, default_value="false")]
+    pub explain: bool,
+
+    /// Print the normalised content and name expressions, as they will be evaluated, and exit
+    /// without running the analysis
+    #[clap(long, default_value="false")]
+    pub print_expr: bool,
+
+    /// Format of the commits in the output file. The reports and the snapshots are only
+    /// written with json and the statistics with json or csv.
+    ///
+    /// json - a single array of commits, sorted by date, written when the analysis finishes
+    ///
+    /// jsonl - one commit per line, written as the commits are analysed, between a line with the
+    /// metadata of the run when it starts and one with its counts when it finishes
+    ///
+    /// csv, parquet - one row per commit, file and label, with the columns commit, date, size,
+    /// file, is_test, label, language, framework, test_cases, author and committer. The columns
+    /// of the optional details are empty when the details are not enabled. The metadata of the
+    /// run is written to <output>.metadata.json for csv, and to the file metadata for parquet
+    ///
+    /// sqlite - tables of repositories, commits, files, file_commits, labels and the metadata
+    /// of the run of each repository, with indexes on the commit date and the file path. With
+    /// --append, several repositories can be written to the same database, the rows of a
+    /// repository written again are replaced
+    #[clap(long, value_enum, default_value="json")]
+    pub format: OutputFormat,
+
+    /// Write the JSON Lines oldest commit first, by date with --history-order time or parents
+    /// before children with --history-order topological, instead of as soon as each commit is
+    /// analysed. Only valid with --format jsonl
+    #[clap(long, default_value="false")]
+    pub ordered: bool,
+
     /// Delete duplicate files from the analysis
     #[clap(long, default_value="false")]
     pub delete_duplicates: bool,
@@ -77,13 +260,93 @@ pub struct Arguments {
     /// NOT(expression) - the expression must evaluate to false
     ///
     /// AND(OR(expression1, expression2), NOT(expression3)) - a combination of AND, OR and NOT
-    pub regex_pattern: String,
+    ///
+    /// Named expressions can be defined before the expression, one per line, and referenced
+    /// with $name. The definitions are shared with the --evaluate-name expression.
+    ///
+    /// DEF name = expression
+    #[clap(required=true)]
+    pub regex_pattern: Option<String>,
 
     /// Output file
-    pub output: String,
+    #[clap(required_unless_present="print_expr")]
+    pub output: Option<String>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Inspect or delete the analysis cache
+    Cache {
+        #[command(subcommand)]
+        action: CacheAction,
+    },
+    /// Merge output files into a JSON Lines file, keeping the first occurrence of each commit,
+    /// sorted by date. Reads the JSON and JSON Lines outputs
+    Merge {
+        /// Output files to merge
+        #[clap(required=true)]
+        files: Vec<String>,
+
+        /// Merged file
+        #[clap(short, long)]
+        output: String,
+
+        #[command(flatten)]
+        filter: FilterArguments,
+    },
+    /// Print the totals of the commits of output files, merged as by the merge command
+    Summary {
+        /// Output files to summarise
+        #[clap(required=true)]
+        files: Vec<String>,
+
+        #[command(flatten)]
+        filter: FilterArguments,
+    },
+}
+
+/// Selection of the commits read from output files
+#[derive(Args)]
+pub struct FilterArguments {
+    /// Only the commits from this date (format: YYYY-MM-DD)
+    #[clap(long, value_parser=parse_date)]
+    pub since: Option<NaiveDate>,
+
+    /// Only the commits until this date, included (format: YYYY-MM-DD)
+    #[clap(long, value_parser=parse_date)]
+    pub until: Option<NaiveDate>,
+
+    /// Only the files whose name or path matches this regular expression
+    #[clap(long)]
+    pub path: Option<String>,
+
+    /// Only the files with this label
+    #[clap(long)]
+    pub label: Option<String>,
+}
+
+#[derive(Subcommand)]
+pub enum CacheAction {
+    /// Print the number of cached commits and the disk usage of each configuration
+    Stats {
+        /// Cache directory
+        dir: String,
+    },
+    /// Delete the cache directory
+    Clear {
+        /// Cache directory
+        dir: String,
+    },
 }
 
 fn parse_date(s: &str) -> Result<NaiveDate> {
     NaiveDate::parse_from_str(s, "%Y-%m-%d").
         map_err(|e| color_eyre::eyre::eyre!("Invalid date format: {}", e))
-}
\ No newline at end of file
+}
+
+fn parse_label(s: &str) -> Result<(String, String)> {
+    s.split_once('=')
+        .filter(|(label, _)| !label.is_empty())
+        .map(|(label, expr)| (label.to_string(), expr.to_string()))
+        .ok_or_else(|| color_eyre::eyre::eyre!("Invalid classifier, expected LABEL=EXPRESSION: {}", s))
+}
@@ -0,0 +1,133 @@
+// Cache of the classification of blobs by object id. A blob has the same content in every
+// commit it appears in (cherry-picks, reverts, re-added files), so its content only needs to
+// be scanned once.
+
+use std::collections::HashMap;
+use std::io::{BufReader, BufWriter};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::RwLock;
+use git2::Oid;
+use serde::{Deserialize, Serialize};
+use color_eyre::eyre::{eyre, Result};
+
+/// Results of the content expressions for a blob
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct ContentMatches {
+    /// Whether the content matches the expression
+    pub is_test: bool,
+    /// Labels of the classifiers whose content expression matches
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub labels: Vec<String>,
+}
+
+/// Classification of the blobs seen so far, shared between the analysers of every thread.
+/// The fingerprint describes the configuration the results depend on, a cache file written
+/// with another configuration is not reused.
+#[derive(Debug, Default)]
+pub struct BlobCache {
+    fingerprint: String,
+    entries: RwLock<HashMap<Oid, ContentMatches>>,
+    hits: AtomicUsize,
+}
+
+// Layout of the cache file
+#[derive(Serialize, Deserialize)]
+struct CacheFile {
+    fingerprint: String,
+    entries: HashMap<String, ContentMatches>,
+}
+
+impl BlobCache {
+    pub fn new(fingerprint: String) -> BlobCache {
+        BlobCache {
+            fingerprint,
+            ..Default::default()
+        }
+    }
+
+    /// Loads the cache file, starting with an empty cache if it does not exist or was written
+    /// with another fingerprint
+    pub fn load(path: &str, fingerprint: String) -> Result<BlobCache> {
+        if !Path::new(path).exists() {
+            return Ok(BlobCache::new(fingerprint));
+        }
+
+        let reader = BufReader::new(std::fs::File::open(path)?);
+        let file: CacheFile = serde_json::from_reader(reader)
+            .map_err(|e| eyre!("Failed to read the blob cache {}: {}", path, e))?;
+        if file.fingerprint != fingerprint {
+            return Ok(BlobCache::new(fingerprint));
+        }
+
+        let entries = file.entries.into_iter()
+            .map(|(oid, matches)| Ok((Oid::from_str(&oid)?, matches)))
+            .collect::<Result<HashMap<_, _>>>()?;
+
+        Ok(BlobCache {
+            fingerprint,
+            entries: RwLock::new(entries),
+            hits: AtomicUsize::new(0),
+        })
+    }
+
+    pub fn save(&self, path: &str) -> Result<()> {
+        let file = CacheFile {
+            fingerprint: self.fingerprint.clone(),
+            entries: self.entries.read().unwrap().iter()
+                .map(|(oid, matches)| (oid.to_string(), matches.clone()))
+                .collect(),
+        };
+
+        let writer = BufWriter::new(std::fs::File::create(path)?);
+        serde_json::to_writer(writer, &file)?;
+        Ok(())
+    }
+
+    pub fn get(&self, oid: Oid) -> Option<ContentMatches> {
+        let matches = self.entries.read().unwrap().get(&oid).cloned();
+        if matches.is_some() {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+        }
+        matches
+    }
+
+    pub fn insert(&self, oid: Oid, matches: ContentMatches) {
+        self.entries.write().unwrap().insert(oid, matches);
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.read().unwrap().len()
+    }
+
+    /// Number of lookups answered by the cache
+    pub fn hits(&self) -> usize {
+        self.hits.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_save_and_load() {
+        let path = std::env::temp_dir().join(format!("blob_cache_{}.json", std::process::id()));
+        let path = path.to_str().unwrap();
+        let oid = Oid::from_str("0123456789012345678901234567890123456789").unwrap();
+
+        let cache = BlobCache::new("/@Test/".to_string());
+        cache.insert(oid, ContentMatches { is_test: true, labels: vec!["mock".to_string()] });
+        cache.save(path).unwrap();
+
+        let loaded = BlobCache::load(path, "/@Test/".to_string()).unwrap();
+        assert_eq!(loaded.get(oid).unwrap().labels, vec!["mock"]);
+        assert_eq!(loaded.hits(), 1);
+
+        // A different configuration does not reuse the results
+        let other = BlobCache::load(path, "/@Before/".to_string()).unwrap();
+        assert_eq!(other.len(), 0);
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,206 @@
+// Test-to-code co-evolution report: for each production file, whether the tests paired with
+// it change together with it.
+
+use std::collections::{HashMap, HashSet};
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use crate::pairing::is_pair;
+use crate::serialization::NativeDateTimeWrapper;
+
+/// Test and production files changed by a commit
+#[derive(Debug, Clone)]
+pub struct CommitChanges {
+    pub commit: String,
+    /// Position of the commit in the history order
+    pub position: usize,
+    pub author: String,
+    pub date: NaiveDateTime,
+    pub test_files: Vec<String>,
+    pub production_files: Vec<String>,
+    /// Paths imported by each of the test files, in the same order
+    pub test_imports: Vec<Vec<String>>,
+}
+
+/// How a change of a production file relates to the changes of its tests
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CoEvolution {
+    /// A paired test changed in the same commit
+    SameCommit,
+    /// A paired test changed within the window of commits before or after
+    WithinWindow,
+    /// The paired tests changed in the history, but not within the window
+    OutsideWindow,
+    /// No paired test ever changed
+    Never,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ProductionChange {
+    pub commit: String,
+    pub date: NativeDateTimeWrapper,
+    pub status: CoEvolution,
+}
+
+/// Co-evolution summary of a production file
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FileCoEvolution {
+    pub file: String,
+    pub paired_tests: Vec<String>,
+    pub same_commit: usize,
+    pub within_window: usize,
+    pub outside_window: usize,
+    pub never: usize,
+    pub changes: Vec<ProductionChange>,
+}
+
+/// Builds the co-evolution summary of every production file. The changes must be sorted by
+/// position, the window is the number of commits before or after a change in which a test
+/// change still counts.
+pub fn co_evolution_report(changes: &[CommitChanges], window: usize) -> Vec<FileCoEvolution> {
+    // Commits that changed each test file, and the paths each test file ever imported
+    let mut test_commits: HashMap<&str, Vec<usize>> = HashMap::new();
+    for (idx, commit) in changes.iter().enumerate() {
+        for test_file in &commit.test_files {
+            test_commits.entry(test_file).or_default().push(idx);
+        }
+    }
+    let test_imports = collect_test_imports(changes);
+
+    // Commits that changed each production file, in order of first change
+    let mut production_commits: Vec<(&str, Vec<usize>)> = Vec::new();
+    let mut production_index: HashMap<&str, usize> = HashMap::new();
+    for (idx, commit) in changes.iter().enumerate() {
+        for production_file in &commit.production_files {
+            let position = *production_index.entry(production_file).or_insert_with(|| {
+                production_commits.push((production_file, Vec::new()));
+                production_commits.len() - 1
+            });
+            production_commits[position].1.push(idx);
+        }
+    }
+
+    let mut test_files: Vec<&str> = test_commits.keys().copied().collect();
+    test_files.sort();
+
+    let mut report = Vec::new();
+    for (production_file, commits) in production_commits {
+        let paired_tests: Vec<&str> = test_files.iter()
+            .copied()
+            .filter(|test_file| is_pair(test_file, production_file, &test_imports[test_file]))
+            .collect();
+
+        // Commits that changed any of the paired tests
+        let mut paired_commits: Vec<usize> = paired_tests.iter()
+            .flat_map(|test_file| test_commits[test_file].iter().copied())
+            .collect();
+        paired_commits.sort();
+        paired_commits.dedup();
+
+        let mut summary = FileCoEvolution {
+            file: production_file.to_string(),
+            paired_tests: paired_tests.iter().map(|test_file| test_file.to_string()).collect(),
+            same_commit: 0,
+            within_window: 0,
+            outside_window: 0,
+            never: 0,
+            changes: Vec::new(),
+        };
+
+        for idx in commits {
+            let status = co_evolution(idx, &paired_commits, window);
+            match status {
+                CoEvolution::SameCommit => summary.same_commit += 1,
+                CoEvolution::WithinWindow => summary.within_window += 1,
+                CoEvolution::OutsideWindow => summary.outside_window += 1,
+                CoEvolution::Never => summary.never += 1,
+            }
+
+            summary.changes.push(ProductionChange {
+                commit: changes[idx].commit.clone(),
+                date: NativeDateTimeWrapper(changes[idx].date),
+                status,
+            });
+        }
+
+        report.push(summary);
+    }
+
+    report
+}
+
+/// Returns the paths each test file imported in any of its versions
+pub fn collect_test_imports(changes: &[CommitChanges]) -> HashMap<&str, Vec<String>> {
+    let mut test_imports: HashMap<&str, HashSet<&String>> = HashMap::new();
+    for commit in changes {
+        for (test_file, imports) in commit.test_files.iter().zip(commit.test_imports.iter()) {
+            test_imports.entry(test_file).or_default().extend(imports);
+        }
+    }
+
+    test_imports.into_iter()
+        .map(|(test_file, imports)| (test_file, imports.into_iter().cloned().collect()))
+        .collect()
+}
+
+// Classifies the change of a production file at the given commit, given the sorted commits
+// that changed its paired tests
+fn co_evolution(idx: usize, paired_commits: &[usize], window: usize) -> CoEvolution {
+    if paired_commits.is_empty() {
+        CoEvolution::Never
+    } else if paired_commits.binary_search(&idx).is_ok() {
+        CoEvolution::SameCommit
+    } else if paired_commits.iter().any(|paired| paired.abs_diff(idx) <= window) {
+        CoEvolution::WithinWindow
+    } else {
+        CoEvolution::OutsideWindow
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn changes(commit: &str, test_files: &[&str], production_files: &[&str]) -> CommitChanges {
+        CommitChanges {
+            commit: commit.to_string(),
+            position: 0,
+            author: String::new(),
+            date: NaiveDateTime::default(),
+            test_files: test_files.iter().map(|file| file.to_string()).collect(),
+            production_files: production_files.iter().map(|file| file.to_string()).collect(),
+            test_imports: vec![Vec::new(); test_files.len()],
+        }
+    }
+
+    #[test]
+    fn test_co_evolution_report() {
+        let history = vec![
+            changes("a", &["FooTest.java"], &["Foo.java", "Bar.java"]),
+            changes("b", &[], &["Foo.java"]),
+            changes("c", &[], &["Other.java"]),
+            changes("d", &[], &["Foo.java"]),
+            changes("e", &["FooTest.java"], &[]),
+            changes("f", &[], &["Foo.java"]),
+        ];
+
+        let report = co_evolution_report(&history, 1);
+        assert_eq!(report.len(), 3);
+
+        let foo = &report[0];
+        assert_eq!(foo.file, "Foo.java");
+        assert_eq!(foo.paired_tests, vec!["FooTest.java"]);
+        let statuses: Vec<CoEvolution> = foo.changes.iter().map(|change| change.status).collect();
+        assert_eq!(statuses, vec![CoEvolution::SameCommit, CoEvolution::WithinWindow, CoEvolution::WithinWindow, CoEvolution::WithinWindow]);
+
+        let bar = &report[1];
+        assert!(bar.paired_tests.is_empty());
+        assert_eq!(bar.never, 1);
+    }
+
+    #[test]
+    fn test_outside_window() {
+        assert_eq!(co_evolution(5, &[0, 9], 2), CoEvolution::OutsideWindow);
+        assert_eq!(co_evolution(5, &[0, 7], 2), CoEvolution::WithinWindow);
+    }
+}
@@ -0,0 +1,159 @@
+// Persistent cache of the analysis of each commit. The results are stored in a directory per
+// configuration, named after a hash of the configuration, with a file per commit. A run only
+// analyses the commits that are missing from the directory of its configuration.
+
+use std::io::{BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+use color_eyre::eyre::Result;
+use crate::serialization::{CommitData, SCHEMA_VERSION};
+
+// Describes the configuration in the directory of the configuration
+const CONFIGURATION_FILE: &str = "configuration.txt";
+
+pub struct CommitCache {
+    dir: PathBuf,
+}
+
+/// Number of cached commits and disk usage of a configuration
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigurationStats {
+    pub hash: String,
+    pub configuration: String,
+    pub commits: usize,
+    pub bytes: u64,
+}
+
+impl CommitCache {
+    /// Opens the cache of the configuration, creating its directory if needed. The
+    /// configuration must describe everything the results of process_commit depend on. The
+    /// version of the crate and of the output schema are added to it, so the entries of
+    /// another version are not read.
+    pub fn open(cache_dir: &str, configuration: &str) -> Result<CommitCache> {
+        let configuration = format!(
+            "version: {}, schema version: {}\n{}", env!("CARGO_PKG_VERSION"), SCHEMA_VERSION, configuration,
+        );
+        let dir = Path::new(cache_dir).join(configuration_hash(&configuration));
+        std::fs::create_dir_all(&dir)?;
+        std::fs::write(dir.join(CONFIGURATION_FILE), configuration)?;
+
+        Ok(CommitCache { dir })
+    }
+
+    /// Returns the cached result of the commit, None if it was not analysed yet. A commit
+    /// without relevant data is cached as Some(None).
+    pub fn get(&self, commit: git2::Oid) -> Option<Option<CommitData>> {
+        let file = std::fs::File::open(self.commit_path(commit)).ok()?;
+
+        // An unreadable entry is analysed again
+        serde_json::from_reader(BufReader::new(file)).ok()
+    }
+
+    pub fn put(&self, commit: git2::Oid, commit_data: &Option<CommitData>) -> Result<()> {
+        // Write to a temporary file first, so an interrupted run leaves no partial entry
+        let path = self.commit_path(commit);
+        let temporary_path = path.with_extension("tmp");
+
+        let writer = BufWriter::new(std::fs::File::create(&temporary_path)?);
+        serde_json::to_writer(writer, commit_data)?;
+        std::fs::rename(temporary_path, path)?;
+        Ok(())
+    }
+
+    fn commit_path(&self, commit: git2::Oid) -> PathBuf {
+        self.dir.join(format!("{}.json", commit))
+    }
+}
+
+/// Returns the statistics of every configuration in the cache directory
+pub fn cache_stats(cache_dir: &str) -> Result<Vec<ConfigurationStats>> {
+    let mut stats = Vec::new();
+    if !Path::new(cache_dir).exists() {
+        return Ok(stats);
+    }
+
+    for entry in std::fs::read_dir(cache_dir)? {
+        let entry = entry?;
+        if !entry.file_type()?.is_dir() {
+            continue;
+        }
+
+        let configuration = std::fs::read_to_string(entry.path().join(CONFIGURATION_FILE)).unwrap_or_default();
+        let mut configuration_stats = ConfigurationStats {
+            hash: entry.file_name().to_string_lossy().to_string(),
+            configuration,
+            commits: 0,
+            bytes: 0,
+        };
+
+        for file in std::fs::read_dir(entry.path())? {
+            let file = file?;
+            configuration_stats.bytes += file.metadata()?.len();
+            if file.path().extension().is_some_and(|extension| extension == "json") {
+                configuration_stats.commits += 1;
+            }
+        }
+
+        stats.push(configuration_stats);
+    }
+
+    stats.sort_by(|a, b| a.hash.cmp(&b.hash));
+    Ok(stats)
+}
+
+/// Deletes the cache directory, returning the number of commits that were cached
+pub fn clear_cache(cache_dir: &str) -> Result<usize> {
+    let commits = cache_stats(cache_dir)?.iter().map(|stats| stats.commits).sum();
+    if Path::new(cache_dir).exists() {
+        std::fs::remove_dir_all(cache_dir)?;
+    }
+
+    Ok(commits)
+}
+
+// 64-bit FNV-1a hash of the configuration. Unlike the hasher of the standard library, it is
+// stable across Rust versions, so the cache stays valid after an upgrade.
+fn configuration_hash(configuration: &str) -> String {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in configuration.bytes() {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+
+    format!("{:016x}", hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cache() {
+        let dir = std::env::temp_dir().join(format!("commit_cache_{}", std::process::id()));
+        let dir = dir.to_str().unwrap();
+        let analysed = git2::Oid::from_str("0123456789012345678901234567890123456789").unwrap();
+        let missing = git2::Oid::from_str("9876543210987654321098765432109876543210").unwrap();
+
+        let cache = CommitCache::open(dir, "/@Test/").unwrap();
+        cache.put(analysed, &None).unwrap();
+        assert_eq!(cache.get(analysed).map(|commit_data| commit_data.is_none()), Some(true));
+        assert!(cache.get(missing).is_none());
+
+        // Another configuration has its own entries
+        let other = CommitCache::open(dir, "/@Before/").unwrap();
+        assert!(other.get(analysed).is_none());
+
+        let stats = cache_stats(dir).unwrap();
+        assert_eq!(stats.len(), 2);
+        assert_eq!(stats.iter().map(|stats| stats.commits).sum::<usize>(), 1);
+        assert!(stats.iter().all(|stats| stats.configuration.starts_with(&format!("version: {}", env!("CARGO_PKG_VERSION")))));
+
+        assert_eq!(clear_cache(dir).unwrap(), 1);
+        assert!(cache_stats(dir).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_configuration_hash() {
+        assert_eq!(configuration_hash(""), "cbf29ce484222325");
+        assert_ne!(configuration_hash("/@Test/"), configuration_hash("/@Before/"));
+    }
+}
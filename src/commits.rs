@@ -1,9 +1,34 @@
-use chrono::{NaiveDate, NaiveDateTime};
+use chrono::{DateTime, NaiveDate};
 use git2::{Commit, Repository, Sort};
 use color_eyre::eyre::Result;
+use serde::Serialize;
+
+/// Order in which the history is walked
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum HistoryOrder {
+    /// By commit time
+    Time,
+    /// Parents before their children, regardless of the commit times
+    Topological,
+}
+
+/// Returns the commit the branch points to. The branch can be given as several alternative
+/// names separated by '|', the first one that exists is used.
+pub fn find_branch_head(repo: &Repository, branch: &str) -> Result<git2::Oid, git2::Error> {
+    for branch_name in branch.split('|') {
+        let branch_ref = repo.find_branch(branch_name, git2::BranchType::Local);
+        if let Ok(branch) = branch_ref {
+            return Ok(branch.get().target().unwrap());
+        }
+    }
+
+    Err(git2::Error::from_str("No branch found"))
+}
 
 /// Returns an iterator over the commits in the repository for a given branch.
-/// The commits are returned in chronological order.
+/// The commits are returned in the given order, from the newest to the oldest unless
+/// oldest_first is set, as the reports that compare commits with the earlier ones need.
 /// An optional start and end date can be specified.
 /// An optional start and end commit can be specified.
 pub fn get_commit_walker<'a>(
@@ -12,16 +37,23 @@ pub fn get_commit_walker<'a>(
     start_date: Option<NaiveDate>,
     end_date: Option<NaiveDate>,
     start_commit: Option<String>,
+    order: HistoryOrder,
+    oldest_first: bool,
 ) -> Result<impl Iterator<Item=Commit<'a>>, git2::Error> {
 
-    let possible_branch_names = branch.split('|').collect::<Vec<&str>>();
-
     // Initialize the walker
     let mut revwalk = repo.revwalk()?;
 
 
-    // Set sorting to chronological order
-    revwalk.set_sorting(Sort::TIME)?;
+    // Set sorting to chronological or topological order
+    let mut sorting = match order {
+        HistoryOrder::Time => Sort::TIME,
+        HistoryOrder::Topological => Sort::TOPOLOGICAL,
+    };
+    if oldest_first {
+        sorting |= Sort::REVERSE;
+    }
+    revwalk.set_sorting(sorting)?;
 
     // If a start commit is specified, use it
     if let Some(start_commit) = start_commit {
@@ -29,20 +61,7 @@ pub fn get_commit_walker<'a>(
     }
 
     // Use the branch reference as the starting point
-    let mut has_branch = false;
-    for branch_name in possible_branch_names {
-        let branch_ref = repo.find_branch(branch_name, git2::BranchType::Local);
-        if let Ok(branch) = branch_ref {
-            let branch_commit = branch.get().target().unwrap();
-            revwalk.push(branch_commit)?;
-            has_branch = true;
-            break;
-        }
-    }
-
-    if !has_branch {
-        return Err(git2::Error::from_str("No branch found"));
-    }
+    revwalk.push(find_branch_head(repo, branch)?)?;
 
     // Transform the oids into commits and filter out commits that are not in the specified date range
     let commits = revwalk
@@ -51,8 +70,8 @@ pub fn get_commit_walker<'a>(
         .filter_map(|commit| commit.ok())
         .filter(move |commit| {
             let commit_date_seconds = commit.time().seconds();
-            let commit_date = NaiveDateTime::from_timestamp_opt(commit_date_seconds, 0)
-                .map(|dt| dt.date()).unwrap();
+            let commit_date = DateTime::from_timestamp(commit_date_seconds, 0)
+                .map(|dt| dt.date_naive()).unwrap();
 
             if let Some(start_date) = start_date {
                 if commit_date < start_date {
@@ -72,21 +91,64 @@ pub fn get_commit_walker<'a>(
     Ok(commits)
 }
 
+/// The content an expression is evaluated against for each modified file.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum MatchTarget {
+    /// The full content of the file after the commit
+    Blob,
+    /// Only the lines added to the file by the commit
+    AddedLines,
+    /// Only the lines removed from the file by the commit
+    RemovedLines,
+}
+
+impl MatchTarget {
+    /// Whether a file with the given modification type has content to match for this target
+    pub fn accepts(&self, modification_type: git2::Delta) -> bool {
+        match self {
+            MatchTarget::Blob => modification_type == git2::Delta::Added,
+            MatchTarget::AddedLines => matches!(modification_type, git2::Delta::Added | git2::Delta::Modified),
+            MatchTarget::RemovedLines => matches!(modification_type, git2::Delta::Modified | git2::Delta::Deleted),
+        }
+    }
+
+    // The diff line origin that is collected for this target, if any
+    fn line_origin(&self) -> Option<char> {
+        match self {
+            MatchTarget::Blob => None,
+            MatchTarget::AddedLines => Some('+'),
+            MatchTarget::RemovedLines => Some('-'),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct CommitFile {
     pub name: String,
     pub oid: git2::Oid,
-    pub modification_type: git2::Delta
+    /// The blob before the commit, zero for added files
+    pub old_oid: git2::Oid,
+    pub modification_type: git2::Delta,
+    /// The added or removed lines of the diff, depending on the match target.
+    /// None when matching against the blob.
+    pub diff_lines: Option<Vec<u8>>,
 }
 
-pub fn get_modified_files(repo: &Repository, commit: &Commit, full_names: bool) -> Result<(Vec<CommitFile>, usize)> {
+/// Returns the files changed by the commit. Blobs larger than the maximum size are treated as
+/// binary by the diff, so their lines are neither counted nor collected.
+pub fn get_modified_files(repo: &Repository, commit: &Commit, full_names: bool, match_target: MatchTarget, max_blob_size: Option<u64>) -> Result<(Vec<CommitFile>, usize)> {
     let commit_tree = commit.tree()?;
     let n_parents = commit.parent_count();
 
     if n_parents == 1 {
         let parent_commit = commit.parent(0)?;
         let parent_tree = parent_commit.tree()?;
-        let diff = repo.diff_tree_to_tree(Some(&parent_tree), Some(&commit_tree), None)?;
+        let mut diff_options = git2::DiffOptions::new();
+        if let Some(max_blob_size) = max_blob_size {
+            diff_options.max_size(max_blob_size.try_into().unwrap_or(i64::MAX));
+        }
+        let diff = repo.diff_tree_to_tree(Some(&parent_tree), Some(&commit_tree), Some(&mut diff_options))?;
         let diff_deltas = diff.deltas();
 
         let stats = diff.stats()?;
@@ -94,7 +156,7 @@ pub fn get_modified_files(repo: &Repository, commit: &Commit, full_names: bool)
 
         let mut modified_files = Vec::new();
 
-        for delta in diff_deltas {
+        for (idx, delta) in diff_deltas.enumerate() {
 
             let file_name = if full_names {
                 delta.new_file().path().unwrap().to_str().unwrap().to_string()
@@ -102,10 +164,20 @@ pub fn get_modified_files(repo: &Repository, commit: &Commit, full_names: bool)
                 delta.new_file().path().unwrap().file_name().unwrap().to_str().unwrap().to_string()
             };
 
+            // Collect the lines of the hunks only when they are going to be matched
+            let diff_lines = match match_target.line_origin() {
+                Some(origin) if match_target.accepts(delta.status()) => {
+                    Some(get_diff_lines(&diff, idx, origin)?)
+                }
+                _ => None,
+            };
+
             modified_files.push(CommitFile {
                 name: file_name,
                 oid: delta.new_file().id(),
+                old_oid: delta.old_file().id(),
                 modification_type: delta.status(),
+                diff_lines,
             });
         }
 
@@ -116,10 +188,30 @@ pub fn get_modified_files(repo: &Repository, commit: &Commit, full_names: bool)
     }
 }
 
+// Concatenates the lines of a delta's hunks that have the given origin ('+' or '-')
+fn get_diff_lines(diff: &git2::Diff, idx: usize, origin: char) -> Result<Vec<u8>> {
+    let mut lines = Vec::new();
+
+    if let Some(patch) = git2::Patch::from_diff(diff, idx)? {
+        for hunk_idx in 0..patch.num_hunks() {
+            for line_idx in 0..patch.num_lines_in_hunk(hunk_idx)? {
+                let line = patch.line_in_hunk(hunk_idx, line_idx)?;
+                if line.origin() == origin {
+                    lines.extend_from_slice(line.content());
+                }
+            }
+        }
+    }
+
+    Ok(lines)
+}
+
 // Wraps the commit data with Send + Sync
 #[derive(Debug, Clone)]
 pub struct CommitSendSync<'a> {
-    pub commit: Commit<'a>
+    pub commit: Commit<'a>,
+    /// Position of the commit in the history order
+    pub position: usize,
 }
 
 unsafe impl<'a> Send for CommitSendSync<'a> {}
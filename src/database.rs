@@ -0,0 +1,210 @@
+// SQLite output of the commits, in normalised tables so several runs and repositories can be
+// queried together:
+//
+// repositories  id, path
+// commits       id, repository_id, hash, date, size, author, committer
+// files         id, repository_id, path
+// file_commits  commit_id, file_id, is_test, language, framework, test_cases
+// labels        commit_id, file_id, label
+// metadata      repository_id, schema_version, tool_version, branch, head, configuration,
+//               start_time, end_time, commits, results, skipped_files
+//
+// A file_commits row is an event of a file in a commit, and the labels are those of the event.
+// The metadata row of a repository describes the run that wrote its commits, with the
+// configuration and the skipped files as JSON.
+
+use std::path::Path;
+use color_eyre::eyre::Result;
+use rusqlite::{params, Connection};
+use crate::serialization::{CommitData, RunMetadata};
+use crate::tabular::rows;
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS repositories (
+        id INTEGER PRIMARY KEY,
+        path TEXT NOT NULL UNIQUE
+    );
+    CREATE TABLE IF NOT EXISTS commits (
+        id INTEGER PRIMARY KEY,
+        repository_id INTEGER NOT NULL REFERENCES repositories(id),
+        hash TEXT NOT NULL,
+        date TEXT NOT NULL,
+        size INTEGER NOT NULL,
+        author TEXT,
+        committer TEXT,
+        UNIQUE (repository_id, hash)
+    );
+    CREATE TABLE IF NOT EXISTS files (
+        id INTEGER PRIMARY KEY,
+        repository_id INTEGER NOT NULL REFERENCES repositories(id),
+        path TEXT NOT NULL,
+        UNIQUE (repository_id, path)
+    );
+    CREATE TABLE IF NOT EXISTS file_commits (
+        commit_id INTEGER NOT NULL REFERENCES commits(id),
+        file_id INTEGER NOT NULL REFERENCES files(id),
+        is_test INTEGER NOT NULL,
+        language TEXT,
+        framework TEXT,
+        test_cases INTEGER,
+        PRIMARY KEY (commit_id, file_id)
+    );
+    CREATE TABLE IF NOT EXISTS labels (
+        commit_id INTEGER NOT NULL,
+        file_id INTEGER NOT NULL,
+        label TEXT NOT NULL,
+        PRIMARY KEY (commit_id, file_id, label),
+        FOREIGN KEY (commit_id, file_id) REFERENCES file_commits(commit_id, file_id)
+    );
+    CREATE TABLE IF NOT EXISTS metadata (
+        repository_id INTEGER PRIMARY KEY REFERENCES repositories(id),
+        schema_version INTEGER NOT NULL,
+        tool_version TEXT NOT NULL,
+        branch TEXT NOT NULL,
+        head TEXT NOT NULL,
+        configuration TEXT NOT NULL,
+        start_time TEXT NOT NULL,
+        end_time TEXT NOT NULL,
+        commits INTEGER NOT NULL,
+        results INTEGER NOT NULL,
+        skipped_files TEXT NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS commits_date ON commits(date);
+    CREATE INDEX IF NOT EXISTS files_path ON files(path);
+";
+
+/// Writes the commits of the repository of the run to the database, with the metadata of the
+/// run. Without append, the database is created anew. With append, the commits of the other
+/// repositories are kept, and those of the repository are replaced, so no rows of a run with
+/// another configuration remain.
+pub fn write_to_database(commits: &[CommitData], metadata: &RunMetadata, file_path: &str, append: bool) -> Result<()> {
+    // The repositories are identified by their absolute path
+    let repository = &metadata.repository.path;
+
+    if !append && Path::new(file_path).exists() {
+        std::fs::remove_file(file_path)?;
+    }
+
+    let mut connection = Connection::open(file_path)?;
+    connection.execute_batch(SCHEMA)?;
+
+    let transaction = connection.transaction()?;
+    transaction.execute("INSERT OR IGNORE INTO repositories (path) VALUES (?1)", params![repository])?;
+    let repository_id: i64 = transaction.query_row(
+        "SELECT id FROM repositories WHERE path = ?1",
+        params![repository],
+        |row| row.get(0),
+    )?;
+    delete_repository_rows(&transaction, repository_id)?;
+    transaction.execute(
+        "INSERT OR REPLACE INTO metadata (repository_id, schema_version, tool_version, branch, head, configuration, \
+            start_time, end_time, commits, results, skipped_files) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+        params![
+            repository_id, metadata.schema_version, metadata.tool_version, metadata.repository.branch,
+            metadata.repository.head, metadata.configuration.to_string(),
+            metadata.start_time.0.format("%Y-%m-%d %H:%M:%S UTC").to_string(), metadata.end_time.0.format("%Y-%m-%d %H:%M:%S UTC").to_string(),
+            metadata.counts.commits, metadata.counts.results, serde_json::to_string(&metadata.counts.skipped_files)?,
+        ],
+    )?;
+
+    {
+        let mut insert_commit = transaction.prepare_cached("INSERT OR IGNORE INTO commits (repository_id, hash, date, size, author, committer) VALUES (?1, ?2, ?3, ?4, ?5, ?6)")?;
+        let mut find_commit = transaction.prepare_cached("SELECT id FROM commits WHERE repository_id = ?1 AND hash = ?2")?;
+        let mut insert_file = transaction.prepare_cached("INSERT OR IGNORE INTO files (repository_id, path) VALUES (?1, ?2)")?;
+        let mut find_file = transaction.prepare_cached("SELECT id FROM files WHERE repository_id = ?1 AND path = ?2")?;
+        let mut insert_file_commit = transaction.prepare_cached(
+            "INSERT OR IGNORE INTO file_commits (commit_id, file_id, is_test, language, framework, test_cases) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        )?;
+        let mut insert_label = transaction.prepare_cached("INSERT OR IGNORE INTO labels (commit_id, file_id, label) VALUES (?1, ?2, ?3)")?;
+
+        for row in rows(commits) {
+            insert_commit.execute(params![repository_id, row.commit, row.date, row.size, row.author, row.committer])?;
+            let commit_id: i64 = find_commit.query_row(params![repository_id, row.commit], |row| row.get(0))?;
+
+            insert_file.execute(params![repository_id, row.file])?;
+            let file_id: i64 = find_file.query_row(params![repository_id, row.file], |row| row.get(0))?;
+
+            insert_file_commit.execute(params![commit_id, file_id, row.is_test, row.language, row.framework, row.test_cases])?;
+
+            if let Some(label) = row.label {
+                insert_label.execute(params![commit_id, file_id, label])?;
+            }
+        }
+    }
+
+    transaction.commit()?;
+    Ok(())
+}
+
+// Deletes the commits and files of the repository written by a previous run
+fn delete_repository_rows(connection: &Connection, repository_id: i64) -> Result<()> {
+    let commits = "SELECT id FROM commits WHERE repository_id = ?1";
+    connection.execute(&format!("DELETE FROM labels WHERE commit_id IN ({})", commits), params![repository_id])?;
+    connection.execute(&format!("DELETE FROM file_commits WHERE commit_id IN ({})", commits), params![repository_id])?;
+    connection.execute("DELETE FROM commits WHERE repository_id = ?1", params![repository_id])?;
+    connection.execute("DELETE FROM files WHERE repository_id = ?1", params![repository_id])?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+    use crate::serialization::RunCounts;
+
+    fn commit_data(commit: &str, test_files: &[&str]) -> CommitData {
+        let mut labels = BTreeMap::new();
+        labels.insert("mock".to_string(), vec![test_files[0].to_string()]);
+
+        CommitData { labels: Some(labels), ..CommitData::with_test_files(commit, test_files) }
+    }
+
+    fn metadata(repository: &str) -> RunMetadata {
+        RunMetadata {
+            configuration: serde_json::json!({"format": "sqlite"}),
+            counts: RunCounts { commits: 2, results: 2, ..Default::default() },
+            ..RunMetadata::for_repository(repository)
+        }
+    }
+
+    #[test]
+    fn test_write_to_database() {
+        let path = std::env::temp_dir().join(format!("commits_{}.sqlite", std::process::id()));
+        let path = path.to_str().unwrap();
+        let count = |table: &str| -> i64 {
+            let connection = Connection::open(path).unwrap();
+            connection.query_row(&format!("SELECT COUNT(*) FROM {}", table), [], |row| row.get(0)).unwrap()
+        };
+
+        let commits = vec![commit_data("a", &["FooTest.java", "BarTest.java"]), commit_data("b", &["FooTest.java"])];
+        write_to_database(&commits, &metadata("/repos/first"), path, false).unwrap();
+        assert_eq!(count("commits"), 2);
+        assert_eq!(count("files"), 2);
+        assert_eq!(count("file_commits"), 3);
+        assert_eq!(count("labels"), 2);
+
+        // Appending the same repository again does not duplicate it, another one is added
+        write_to_database(&commits, &metadata("/repos/first"), path, true).unwrap();
+        write_to_database(&commits[..1], &metadata("/repos/second"), path, true).unwrap();
+        assert_eq!(count("repositories"), 2);
+        assert_eq!(count("commits"), 3);
+        assert_eq!(count("file_commits"), 5);
+
+        // A run with another configuration replaces the rows of the repository
+        let mut other_labels = commit_data("b", &["FooTest.java"]);
+        other_labels.labels = None;
+        write_to_database(&[other_labels], &metadata("/repos/first"), path, true).unwrap();
+        assert_eq!(count("repositories"), 2);
+        assert_eq!(count("commits"), 2);
+        assert_eq!(count("files"), 3);
+        assert_eq!(count("file_commits"), 3);
+        assert_eq!(count("labels"), 1);
+        assert_eq!(count("metadata"), 2);
+
+        // Without append, the database is replaced
+        write_to_database(&commits[..1], &metadata("/repos/first"), path, false).unwrap();
+        assert_eq!(count("repositories"), 1);
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
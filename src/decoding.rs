@@ -0,0 +1,144 @@
+// Decoding of file contents that may not be valid UTF-8.
+
+use std::borrow::Cow;
+use serde::{Deserialize, Serialize};
+
+/// How the content of files that are not valid UTF-8 is handled
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum Encoding {
+    /// Skip the files that are not valid UTF-8
+    Strict,
+    /// Replace the invalid UTF-8 sequences with the replacement character
+    Lossy,
+    /// Detect UTF-16 from the byte order mark, and decode the files that are not valid UTF-8
+    /// as Latin-1
+    Detect,
+}
+
+// Byte order marks
+const UTF8_BOM: &[u8] = &[0xEF, 0xBB, 0xBF];
+const UTF16LE_BOM: &[u8] = &[0xFF, 0xFE];
+const UTF16BE_BOM: &[u8] = &[0xFE, 0xFF];
+
+/// Files that were not analysed, by reason
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct SkippedFiles {
+    /// Blobs that could not be found in the repository
+    pub missing: usize,
+    /// Blobs libgit2 considers binary
+    pub binary: usize,
+    /// Contents that could not be decoded with the chosen encoding
+    pub undecodable: usize,
+    /// Blobs larger than the maximum blob size
+    pub too_large: usize,
+}
+
+impl SkippedFiles {
+    pub fn add(&mut self, other: &SkippedFiles) {
+        self.missing += other.missing;
+        self.binary += other.binary;
+        self.undecodable += other.undecodable;
+        self.too_large += other.too_large;
+    }
+
+    pub fn total(&self) -> usize {
+        self.missing + self.binary + self.undecodable + self.too_large
+    }
+}
+
+/// Whether the blob is binary. In detect mode, UTF-16 text with a byte order mark is not
+/// binary, although libgit2 considers it so.
+pub fn is_binary(blob: &git2::Blob, encoding: Encoding) -> bool {
+    if encoding == Encoding::Detect && utf16_bom(blob.content()).is_some() {
+        return false;
+    }
+
+    blob.is_binary()
+}
+
+// Returns whether the content starts with a little endian UTF-16 byte order mark, if it starts
+// with one
+fn utf16_bom(content: &[u8]) -> Option<bool> {
+    if content.starts_with(UTF16LE_BOM) {
+        Some(true)
+    } else if content.starts_with(UTF16BE_BOM) {
+        Some(false)
+    } else {
+        None
+    }
+}
+
+/// Decodes the content with the given encoding, None if it cannot be decoded
+pub fn decode(content: &[u8], encoding: Encoding) -> Option<Cow<'_, str>> {
+    match encoding {
+        Encoding::Strict => std::str::from_utf8(content).ok().map(Cow::Borrowed),
+        Encoding::Lossy => Some(String::from_utf8_lossy(content)),
+        Encoding::Detect => {
+            if let Some(little_endian) = utf16_bom(content) {
+                return decode_utf16(&content[2..], little_endian).map(Cow::Owned);
+            }
+
+            let content = content.strip_prefix(UTF8_BOM).unwrap_or(content);
+            match std::str::from_utf8(content) {
+                Ok(content) => Some(Cow::Borrowed(content)),
+                // Every byte is a valid Latin-1 character
+                Err(_) => Some(Cow::Owned(content.iter().map(|&byte| byte as char).collect())),
+            }
+        }
+    }
+}
+
+/// Returns at most the first max_len bytes of the content, ending at a character boundary
+pub fn prefix(content: &str, max_len: usize) -> &str {
+    if content.len() <= max_len {
+        return content;
+    }
+
+    let mut end = max_len;
+    while !content.is_char_boundary(end) {
+        end -= 1;
+    }
+    &content[..end]
+}
+
+fn decode_utf16(content: &[u8], little_endian: bool) -> Option<String> {
+    let units: Vec<u16> = content.chunks_exact(2)
+        .map(|pair| if little_endian {
+            u16::from_le_bytes([pair[0], pair[1]])
+        } else {
+            u16::from_be_bytes([pair[0], pair[1]])
+        })
+        .collect();
+
+    String::from_utf16(&units).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode() {
+        let latin1 = b"// Fran\xe7ais\n@Test";
+        assert_eq!(decode(latin1, Encoding::Strict), None);
+        assert_eq!(decode(latin1, Encoding::Lossy).unwrap(), "// Fran\u{FFFD}ais\n@Test");
+        assert_eq!(decode(latin1, Encoding::Detect).unwrap(), "// Français\n@Test");
+
+        let utf16: Vec<u8> = UTF16LE_BOM.iter().copied()
+            .chain("@Test".encode_utf16().flat_map(|unit| unit.to_le_bytes()))
+            .collect();
+        assert_eq!(decode(&utf16, Encoding::Detect).unwrap(), "@Test");
+
+        let utf8 = [UTF8_BOM, "é".as_bytes()].concat();
+        assert_eq!(decode(&utf8, Encoding::Detect).unwrap(), "é");
+    }
+
+    #[test]
+    fn test_prefix() {
+        assert_eq!(prefix("@Test", 10), "@Test");
+        assert_eq!(prefix("@Test", 2), "@T");
+        // é is two bytes long
+        assert_eq!(prefix("aéb", 2), "a");
+    }
+}
@@ -0,0 +1,140 @@
+// Detects the language of a file from its extension and the test framework it likely uses
+// from its imports and annotations.
+
+use lazy_static::lazy_static;
+use regex::Regex;
+
+/// Language and test framework of a file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Detection {
+    pub language: Option<&'static str>,
+    pub framework: Option<&'static str>,
+}
+
+// A framework is detected if the content of a file of the language matches the pattern
+struct FrameworkRule {
+    language: &'static str,
+    framework: &'static str,
+    pattern: Regex,
+}
+
+impl FrameworkRule {
+    fn new(language: &'static str, framework: &'static str, pattern: &str) -> FrameworkRule {
+        FrameworkRule {
+            language,
+            framework,
+            pattern: Regex::new(pattern).unwrap(),
+        }
+    }
+}
+
+// Spec files that only use the global describe/it/test and expect, without importing a
+// framework, are most likely run by Jest, the default of most JavaScript projects
+const JEST_GLOBALS: &str = r"(?m)^\s*(describe|it|test)\s*\(|\bexpect\(";
+
+lazy_static! {
+    // Rules are tried in order, the first rule of the language that matches wins
+    static ref FRAMEWORK_RULES: Vec<FrameworkRule> = vec![
+        FrameworkRule::new("Java", "JUnit 5", r"import\s+(static\s+)?org\.junit\.jupiter\."),
+        FrameworkRule::new("Java", "TestNG", r"import\s+(static\s+)?org\.testng\."),
+        FrameworkRule::new("Java", "JUnit 4", r"import\s+(static\s+)?org\.junit\.|@RunWith\("),
+        FrameworkRule::new("Java", "JUnit 3", r"import\s+junit\.framework\.|extends\s+TestCase\b"),
+        FrameworkRule::new("Kotlin", "JUnit 5", r"import\s+org\.junit\.jupiter\."),
+        FrameworkRule::new("Kotlin", "JUnit 4", r"import\s+org\.junit\."),
+        FrameworkRule::new("Kotlin", "kotlin.test", r"import\s+kotlin\.test\."),
+        FrameworkRule::new("Groovy", "Spock", r"import\s+spock\.lang\.|extends\s+Specification\b"),
+        FrameworkRule::new("Scala", "ScalaTest", r"import\s+org\.scalatest\."),
+        // pytest files often import unittest.mock, and module level test functions are pytest's
+        FrameworkRule::new("Python", "pytest", r"(?m)^\s*(import\s+pytest|from\s+pytest\s+import)|@pytest\.|^def\s+test_"),
+        FrameworkRule::new("Python", "unittest", r"(?m)^\s*(import\s+unittest|from\s+unittest\s+import)|unittest\.TestCase"),
+        FrameworkRule::new("JavaScript", "Vitest", r#"from\s+['"]vitest['"]"#),
+        FrameworkRule::new("JavaScript", "Jest", r#"from\s+['"]@jest/globals['"]|\bjest\.(fn|mock|spyOn)\("#),
+        FrameworkRule::new("JavaScript", "Mocha", r#"(require\(|from\s+)['"](mocha|chai)['"]"#),
+        FrameworkRule::new("JavaScript", "Jasmine", r"\bjasmine\.|\bspyOn\("),
+        FrameworkRule::new("JavaScript", "Jest", JEST_GLOBALS),
+        FrameworkRule::new("TypeScript", "Vitest", r#"from\s+['"]vitest['"]"#),
+        FrameworkRule::new("TypeScript", "Jest", r#"from\s+['"]@jest/globals['"]|\bjest\.(fn|mock|spyOn)\("#),
+        FrameworkRule::new("TypeScript", "Mocha", r#"(require\(|from\s+)['"](mocha|chai)['"]"#),
+        FrameworkRule::new("TypeScript", "Jasmine", r"\bjasmine\.|\bspyOn\("),
+        FrameworkRule::new("TypeScript", "Jest", JEST_GLOBALS),
+        FrameworkRule::new("Go", "Go testing", r#""testing""#),
+        FrameworkRule::new("Rust", "Rust #[test]", r"#\[(\w+::)?test\]"),
+        FrameworkRule::new("C#", "xUnit", r"using\s+Xunit\s*;"),
+        FrameworkRule::new("C#", "NUnit", r"using\s+NUnit\.Framework\s*;"),
+        FrameworkRule::new("C#", "MSTest", r"using\s+Microsoft\.VisualStudio\.TestTools\.UnitTesting\s*;"),
+        FrameworkRule::new("Ruby", "RSpec", r"\bRSpec\.describe\b|require\s+['\x22](rspec|spec_helper|rails_helper)['\x22]"),
+        FrameworkRule::new("Ruby", "Minitest", r"\bMinitest::Test\b|require\s+['\x22]minitest"),
+        FrameworkRule::new("PHP", "PHPUnit", r"PHPUnit\\Framework\\TestCase|extends\s+TestCase\b"),
+    ];
+}
+
+/// Returns the language of a file given its extension
+pub fn language(extension: &str) -> Option<&'static str> {
+    match extension {
+        "java" => Some("Java"),
+        "kt" | "kts" => Some("Kotlin"),
+        "groovy" => Some("Groovy"),
+        "scala" => Some("Scala"),
+        "py" => Some("Python"),
+        "js" | "jsx" | "mjs" | "cjs" => Some("JavaScript"),
+        "ts" | "tsx" | "mts" | "cts" => Some("TypeScript"),
+        "go" => Some("Go"),
+        "rs" => Some("Rust"),
+        "cs" => Some("C#"),
+        "rb" => Some("Ruby"),
+        "php" => Some("PHP"),
+        _ => None,
+    }
+}
+
+/// Detects the language of a file and the test framework its content uses
+pub fn detect(file_name: &str, content: &str) -> Detection {
+    let language = std::path::Path::new(file_name)
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .and_then(language);
+
+    let framework = language.and_then(|language| {
+        FRAMEWORK_RULES.iter()
+            .filter(|rule| rule.language == language)
+            .find(|rule| rule.pattern.is_match(content))
+            .map(|rule| rule.framework)
+    });
+
+    Detection { language, framework }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_java() {
+        let junit5 = "import org.junit.jupiter.api.Test;\nclass FooTest {}";
+        assert_eq!(detect("FooTest.java", junit5), Detection { language: Some("Java"), framework: Some("JUnit 5") });
+
+        let junit4 = "import static org.junit.Assert.assertEquals;\nimport org.junit.Test;";
+        assert_eq!(detect("src/FooTest.java", junit4).framework, Some("JUnit 4"));
+
+        let testng = "import org.testng.annotations.Test;";
+        assert_eq!(detect("FooTest.java", testng).framework, Some("TestNG"));
+    }
+
+    #[test]
+    fn test_detect_other_languages() {
+        assert_eq!(detect("test_foo.py", "import pytest\n\ndef test_foo():\n    pass").framework, Some("pytest"));
+        assert_eq!(detect("test_foo.py", "import unittest\n\nclass FooTest(unittest.TestCase):\n    def test_foo(self):").framework, Some("unittest"));
+        assert_eq!(detect("test_foo.py", "from unittest import mock\n\ndef test_foo():\n    pass").framework, Some("pytest"));
+        assert_eq!(detect("foo.spec.js", "describe('foo', () => {\n  it('works', () => {\n    expect(foo()).toBe(1);\n  });\n});").framework, Some("Jest"));
+        assert_eq!(detect("foo.spec.js", "const { expect } = require('chai');\ndescribe('foo', () => {});").framework, Some("Mocha"));
+        assert_eq!(detect("foo.test.ts", "import { describe, it } from 'vitest';").framework, Some("Vitest"));
+        assert_eq!(detect("foo_test.go", "import (\n\t\"testing\"\n)").framework, Some("Go testing"));
+        assert_eq!(detect("lib.rs", "#[cfg(test)]\nmod tests {\n    #[test]\n    fn a() {}\n}").framework, Some("Rust #[test]"));
+    }
+
+    #[test]
+    fn test_detect_unknown() {
+        assert_eq!(detect("Foo.java", "class Foo {}"), Detection { language: Some("Java"), framework: None });
+        assert_eq!(detect("Makefile", "test:"), Detection { language: None, framework: None });
+    }
+}
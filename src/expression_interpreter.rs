@@ -1,5 +1,10 @@
+use serde::{Deserialize, Serialize};
 use crate::expression_parser::Expr;
 
+// Recursive evaluation, scanning the line once per regex. The content is matched with the
+// compiled expressions of expression_optimiser, this is the reference implementation they are
+// tested against, and evaluates the name expressions, whose input is too short to gain from a
+// RegexSet.
 pub fn evaluate(expression: &Expr, line: &str) -> bool {
     match expression {
         Expr::And(expressions) => {
@@ -22,7 +27,7 @@ pub fn evaluate(expression: &Expr, line: &str) -> bool {
         }
         Expr::Not(expression) => {
             // NOT evaluates to the opposite of the expression
-            return !evaluate(expression.as_ref(), line);
+            !evaluate(expression.as_ref(), line)
         }
         Expr::Regex(regex) => {
             // A regex evaluates to true if it matches the line
@@ -31,6 +36,78 @@ pub fn evaluate(expression: &Expr, line: &str) -> bool {
     }
 }
 
+/// The evaluation tree of an expression, with the result of each node
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Explanation {
+    /// AND, OR, NOT or REGEX
+    pub operator: String,
+    pub result: bool,
+    /// The regular expression of a REGEX node
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pattern: Option<String>,
+    /// The first match of a REGEX node
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub first_match: Option<MatchSpan>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub children: Vec<Explanation>,
+}
+
+/// Byte span and 1-based line number of a regex match
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct MatchSpan {
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+}
+
+/// Evaluates the expression like `evaluate`, recording the result of every node.
+/// All the children are evaluated, so the tree shows every sub-expression that matched.
+pub fn explain(expression: &Expr, content: &str) -> Explanation {
+    match expression {
+        Expr::And(expressions) => {
+            let children: Vec<Explanation> = expressions.iter().map(|expr| explain(expr, content)).collect();
+            let result = children.iter().all(|child| child.result);
+            Explanation::node("AND", result, children)
+        }
+        Expr::Or(expressions) => {
+            let children: Vec<Explanation> = expressions.iter().map(|expr| explain(expr, content)).collect();
+            let result = children.iter().any(|child| child.result);
+            Explanation::node("OR", result, children)
+        }
+        Expr::Not(expression) => {
+            let child = explain(expression.as_ref(), content);
+            Explanation::node("NOT", !child.result, vec![child])
+        }
+        Expr::Regex(regex) => {
+            let first_match = regex.find(content).map(|m| MatchSpan {
+                start: m.start(),
+                end: m.end(),
+                line: content[..m.start()].matches('\n').count() + 1,
+            });
+
+            Explanation {
+                operator: "REGEX".to_string(),
+                result: first_match.is_some(),
+                pattern: Some(regex.as_str().to_string()),
+                first_match,
+                children: Vec::new(),
+            }
+        }
+    }
+}
+
+impl Explanation {
+    fn node(operator: &str, result: bool, children: Vec<Explanation>) -> Explanation {
+        Explanation {
+            operator: operator.to_string(),
+            result,
+            pattern: None,
+            first_match: None,
+            children,
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use crate::expression_parser::{parse};
@@ -62,4 +139,21 @@ mod tests {
         assert!(!evaluate(&expression, "abclomb"));
         assert!(evaluate(&expression, "deflombxpar"));
     }
+
+    #[test]
+    fn test_explain() {
+        let input = "AND(OR(abc,def),NOT(ghi))";
+        let expression = parse(input).unwrap();
+        let explanation = explain(&expression, "first\nsecond def\n");
+        assert!(explanation.result);
+        assert_eq!(explanation.operator, "AND");
+
+        let or = &explanation.children[0];
+        assert!(!or.children[0].result);
+        assert_eq!(or.children[1].first_match, Some(MatchSpan { start: 13, end: 16, line: 2 }));
+
+        let not = &explanation.children[1];
+        assert!(not.result);
+        assert!(!not.children[0].result);
+    }
 }
\ No newline at end of file
@@ -0,0 +1,160 @@
+// Optimises and compiles an expression tree for faster evaluation.
+//
+// The optimiser flattens nested AND/OR expressions, removes double negations and collapses
+// single-element AND/OR expressions. The compiler then collects every regex leaf of the trees
+// of several expressions into a single RegexSet, so the content is scanned once and each tree
+// is evaluated against the set of matching leaves.
+
+use regex::{Regex, RegexSet};
+use color_eyre::eyre::Result;
+use crate::expression_parser::Expr;
+
+/// Returns an equivalent expression with nested AND/OR flattened and double negations removed
+pub fn optimise(expr: Expr) -> Expr {
+    match expr {
+        Expr::And(expressions) => {
+            let expressions = flatten(expressions, |expr| match expr {
+                Expr::And(expressions) => Ok(expressions),
+                expr => Err(expr),
+            });
+            collapse(expressions, Expr::And)
+        }
+        Expr::Or(expressions) => {
+            let expressions = flatten(expressions, |expr| match expr {
+                Expr::Or(expressions) => Ok(expressions),
+                expr => Err(expr),
+            });
+            collapse(expressions, Expr::Or)
+        }
+        Expr::Not(expression) => match optimise(*expression) {
+            // NOT(NOT(expression)) is equivalent to expression
+            Expr::Not(inner) => *inner,
+            expression => Expr::Not(Box::new(expression)),
+        },
+        Expr::Regex(regex) => Expr::Regex(regex),
+    }
+}
+
+// Optimises the children of an AND/OR expression, splicing in the children of the ones
+// that are of the same kind as the parent
+fn flatten<F>(expressions: Vec<Expr>, same_kind: F) -> Vec<Expr>
+where
+    F: Fn(Expr) -> Result<Vec<Expr>, Expr>,
+{
+    let mut result = Vec::new();
+
+    for expr in expressions {
+        match same_kind(optimise(expr)) {
+            Ok(children) => result.extend(children),
+            Err(expr) => result.push(expr),
+        }
+    }
+
+    result
+}
+
+// An AND/OR with a single element is equivalent to the element itself
+fn collapse(mut expressions: Vec<Expr>, constructor: fn(Vec<Expr>) -> Expr) -> Expr {
+    if expressions.len() == 1 {
+        expressions.pop().unwrap()
+    } else {
+        constructor(expressions)
+    }
+}
+
+// Expression tree whose regex leaves are indices into the RegexSet of a CompiledExpr
+#[derive(Debug, Clone)]
+enum Node {
+    And(Vec<Node>),
+    Or(Vec<Node>),
+    Not(Box<Node>),
+    Leaf(usize),
+}
+
+/// Optimised expressions whose regular expressions are all matched in a single pass
+#[derive(Debug, Clone)]
+pub struct CompiledExprSet {
+    set: RegexSet,
+    roots: Vec<Node>,
+}
+
+impl CompiledExprSet {
+    /// Optimises the expressions and builds the RegexSet of the leaves of all of them
+    pub fn compile(exprs: &[&Expr]) -> Result<CompiledExprSet> {
+        let mut regexes = Vec::new();
+        let roots = exprs.iter()
+            .map(|expr| to_node(optimise((*expr).clone()), &mut regexes))
+            .collect();
+        let set = RegexSet::new(regexes.iter().map(|regex| regex.as_str()))?;
+
+        Ok(CompiledExprSet { set, roots })
+    }
+
+    /// Evaluates every expression against the given content, in the order they were given,
+    /// scanning the content only once
+    pub fn evaluate(&self, content: &str) -> Vec<bool> {
+        let matches = self.set.matches(content);
+        self.roots.iter()
+            .map(|root| evaluate_node(root, &|idx| matches.matched(idx)))
+            .collect()
+    }
+}
+
+// Converts the expression to a node, collecting the regex leaves in order
+fn to_node(expr: Expr, regexes: &mut Vec<Regex>) -> Node {
+    match expr {
+        Expr::And(expressions) => Node::And(expressions.into_iter().map(|expr| to_node(expr, regexes)).collect()),
+        Expr::Or(expressions) => Node::Or(expressions.into_iter().map(|expr| to_node(expr, regexes)).collect()),
+        Expr::Not(expression) => Node::Not(Box::new(to_node(*expression, regexes))),
+        Expr::Regex(regex) => {
+            regexes.push(regex);
+            Node::Leaf(regexes.len() - 1)
+        }
+    }
+}
+
+fn evaluate_node(node: &Node, matched: &dyn Fn(usize) -> bool) -> bool {
+    match node {
+        Node::And(nodes) => nodes.iter().all(|node| evaluate_node(node, matched)),
+        Node::Or(nodes) => nodes.iter().any(|node| evaluate_node(node, matched)),
+        Node::Not(node) => !evaluate_node(node, matched),
+        Node::Leaf(idx) => matched(*idx),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::expression_parser::parse;
+    use crate::expression_interpreter::evaluate;
+    use super::*;
+
+    #[test]
+    fn test_optimise_flattens() {
+        let expression = optimise(parse("AND(AND(abc,OR(def,OR(ghi,jkl))),NOT(NOT(mno)))").unwrap());
+        match expression {
+            Expr::And(expressions) => {
+                assert_eq!(expressions.len(), 3);
+                assert!(matches!(&expressions[1], Expr::Or(inner) if inner.len() == 3));
+                assert!(matches!(&expressions[2], Expr::Regex(regex) if regex.as_str() == "mno"));
+            }
+            _ => panic!("Expected an AND expression"),
+        }
+    }
+
+    #[test]
+    fn test_compiled_matches_evaluate() {
+        let inputs = [
+            "AND(OR(abc,def),NOT(ghi))",
+            "AND(OR(abc,def),lomb,par)",
+            "NOT(NOT(OR(abc,AND(def,NOT(ghi)))))",
+        ];
+        let lines = ["abc", "def", "ghi", "abcghi", "defghi", "deflombxpar", ""];
+
+        let expressions: Vec<Expr> = inputs.iter().map(|input| parse(input).unwrap()).collect();
+        let compiled = CompiledExprSet::compile(&expressions.iter().collect::<Vec<_>>()).unwrap();
+        for line in lines {
+            let expected: Vec<bool> = expressions.iter().map(|expression| evaluate(expression, line)).collect();
+            assert_eq!(compiled.evaluate(line), expected, "{:?}", line);
+        }
+    }
+}
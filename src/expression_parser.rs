@@ -1,23 +1,28 @@
 // Parses the following BNF grammar:
-// <expr> ::= <not> | <and> | <or> | <regex>
+// <program> ::= <definition>* <expr>
+// <definition> ::= "DEF " <name> " = " <expr> "\n"
+// <expr> ::= <not> | <and> | <or> | <ref> | <regex>
 // <or> ::= "OR(" <expr> ("," <expr>)+ ")"
 // <and> ::= "AND(" <expr> ("," <expr>)+ ")"
 // <not> ::= "NOT(" <expr> ")"
+// <ref> ::= "$" <name>
 
 use nom::{
     branch::alt,
-    bytes::complete::{tag, take_while},
+    bytes::complete::{tag, take_while, take_while1},
     character::complete::{char},
     combinator::{map},
-    multi::separated_list0,
-    sequence::{delimited, terminated},
+    multi::{many0, separated_list0},
+    sequence::{delimited, pair, preceded, terminated, tuple},
     IResult,
     Finish
 };
 use regex::Regex;
-use color_eyre::eyre::Result;
-use nom::character::complete::multispace0;
-use nom::combinator::opt;
+use color_eyre::eyre::{eyre, Result};
+use nom::character::complete::{multispace0, multispace1};
+use nom::combinator::{all_consuming, opt};
+use std::collections::HashMap;
+use std::fmt;
 
 
 #[derive(Debug, Clone)]
@@ -28,41 +33,191 @@ pub enum Expr {
     Regex(Regex),
 }
 
+// Two regex leaves are equal if they were built from the same pattern
+impl PartialEq for Expr {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Expr::And(a), Expr::And(b)) => a == b,
+            (Expr::Or(a), Expr::Or(b)) => a == b,
+            (Expr::Not(a), Expr::Not(b)) => a == b,
+            (Expr::Regex(a), Expr::Regex(b)) => a.as_str() == b.as_str(),
+            _ => false,
+        }
+    }
+}
+
+// Prints the expression in the canonical form accepted by `parse`, without spaces between
+// the arguments, as they would be taken as part of the regular expressions.
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Expr::And(expressions) => write_list(f, "AND", expressions),
+            Expr::Or(expressions) => write_list(f, "OR", expressions),
+            Expr::Not(expression) => write!(f, "NOT({})", expression),
+            Expr::Regex(regex) => write!(f, "{}", regex.as_str()),
+        }
+    }
+}
+
+fn write_list(f: &mut fmt::Formatter<'_>, operator: &str, expressions: &[Expr]) -> fmt::Result {
+    write!(f, "{}(", operator)?;
+    for (i, expr) in expressions.iter().enumerate() {
+        if i > 0 {
+            write!(f, ",")?;
+        }
+        write!(f, "{}", expr)?;
+    }
+    write!(f, ")")
+}
+
+/// Parses a single expression, with its own definitions
+#[cfg(test)]
 pub fn parse(input: &str) -> Result<Expr> {
-    let result = parse_expr(input).finish();
-    if let Ok((_, expr)) = result {
-        Ok(expr)
-    } else {
-        Err(color_eyre::eyre::eyre!("Failed to parse expression {}", input))
+    Ok(parse_many(&[input])?.pop().unwrap())
+}
+
+/// Parses several expressions that share their definitions: a name defined in any of them
+/// can be referenced in all of them.
+pub fn parse_many(inputs: &[&str]) -> Result<Vec<Expr>> {
+    let mut programs = Vec::new();
+    for input in inputs {
+        let result = parse_program(input).finish();
+        if let Ok((_, program)) = result {
+            // An empty regular expression would match every file
+            if matches!(&program.expr, Term::Regex(regex) if regex.as_str().is_empty()) {
+                return Err(eyre!("Missing expression after the definitions in {:?}", input));
+            }
+            programs.push(program);
+        } else {
+            return Err(eyre!("Failed to parse expression {}", input));
+        }
+    }
+
+    // Gather the definitions of all the expressions
+    let mut definitions = HashMap::new();
+    for (name, term) in programs.iter().flat_map(|program| program.definitions.iter()) {
+        if definitions.insert(name.as_str(), term).is_some() {
+            return Err(eyre!("Expression ${} is defined more than once", name));
+        }
+    }
+
+    // Check every definition, even the ones that are not referenced
+    for (name, _) in programs.iter().flat_map(|program| program.definitions.iter()) {
+        resolve(&Term::Ref(name.clone()), &definitions, &mut Vec::new())?;
+    }
+
+    programs.iter()
+        .map(|program| resolve(&program.expr, &definitions, &mut Vec::new()))
+        .collect()
+}
+
+// Parse tree of an expression, before the references to definitions are resolved
+enum Term {
+    And(Vec<Term>),
+    Or(Vec<Term>),
+    Not(Box<Term>),
+    Regex(Regex),
+    Ref(String),
+}
+
+struct Program {
+    definitions: Vec<(String, Term)>,
+    expr: Term,
+}
+
+// Replaces the references with the expressions they name. The stack holds the names being
+// resolved, to detect definitions that reference themselves.
+fn resolve(term: &Term, definitions: &HashMap<&str, &Term>, stack: &mut Vec<String>) -> Result<Expr> {
+    match term {
+        Term::And(terms) => Ok(Expr::And(resolve_all(terms, definitions, stack)?)),
+        Term::Or(terms) => Ok(Expr::Or(resolve_all(terms, definitions, stack)?)),
+        Term::Not(term) => Ok(Expr::Not(Box::new(resolve(term, definitions, stack)?))),
+        Term::Regex(regex) => Ok(Expr::Regex(regex.clone())),
+        Term::Ref(name) => {
+            if stack.contains(name) {
+                let cycle: Vec<String> = stack.iter().chain(std::iter::once(name)).map(|name| format!("${}", name)).collect();
+                return Err(eyre!("Cyclic definition: {}", cycle.join(" -> ")));
+            }
+
+            let term = definitions.get(name.as_str())
+                .ok_or_else(|| eyre!("Undefined expression ${}", name))?;
+
+            stack.push(name.clone());
+            let expr = resolve(term, definitions, stack)?;
+            stack.pop();
+
+            Ok(expr)
+        }
     }
 }
 
+fn resolve_all(terms: &[Term], definitions: &HashMap<&str, &Term>, stack: &mut Vec<String>) -> Result<Vec<Expr>> {
+    terms.iter().map(|term| resolve(term, definitions, stack)).collect()
+}
+
+// Parses the definitions followed by the expression, which must be the end of the input
+fn parse_program(input: &str) -> IResult<&str, Program> {
+    all_consuming(map(
+        terminated(
+            pair(
+                many0(parse_definition),
+                parse_expr,
+            ),
+            multispace0,
+        ),
+        |(definitions, expr)| Program { definitions, expr },
+    ))(input)
+}
+
+// Parses a definition, which ends at the end of the line
+fn parse_definition(input: &str) -> IResult<&str, (String, Term)> {
+    map(
+        tuple((
+            tag("DEF"),
+            multispace1,
+            parse_name,
+            multispace0,
+            char('='),
+            multispace0,
+            parse_expr,
+            multispace0,
+        )),
+        |(_, _, name, _, _, _, expr, _)| (name.to_string(), expr),
+    )(input)
+}
+
+// Parses the name of a definition
+fn parse_name(input: &str) -> IResult<&str, &str> {
+    take_while1(|c: char| c.is_alphanumeric() || c == '_' || c == '-')(input)
+}
+
 // Parses a single expression, which can be a regular expression, a NOT expression, an
-// AND expression or an OR expression
-fn parse_expr(input: &str) -> IResult<&str, Expr> {
-    // An expression can be a regular expression, a NOT expression, an AND expression or an OR expression
+// AND expression, an OR expression or a reference to a definition
+fn parse_expr(input: &str) -> IResult<&str, Term> {
+    // An expression can be a regular expression, a NOT expression, an AND expression, an OR expression or a reference
     alt((
         parse_not,
         parse_and,
         parse_or,
+        parse_ref,
         parse_regex,
     ))(input)
 }
 
 // Parses a NOT expression
-fn parse_not(input: &str) -> IResult<&str, Expr> {
+fn parse_not(input: &str) -> IResult<&str, Term> {
     map(
         delimited(
             tag("NOT("),
             parse_expr,
             char(')'),
         ),
-        |expr| Expr::Not(Box::new(expr)),
+        |expr| Term::Not(Box::new(expr)),
     )(input)
 }
 
 // Parses an AND expression
-fn parse_and(input: &str) -> IResult<&str, Expr> {
+fn parse_and(input: &str) -> IResult<&str, Term> {
     map(
         delimited(
             tag("AND("),
@@ -72,12 +227,12 @@ fn parse_and(input: &str) -> IResult<&str, Expr> {
             ),
             char(')'),
         ),
-        Expr::And,
+        Term::And,
     )(input)
 }
 
 // Parses an OR expression
-fn parse_or(input: &str) -> IResult<&str, Expr> {
+fn parse_or(input: &str) -> IResult<&str, Term> {
     map(
         delimited(
             tag("OR("),
@@ -87,17 +242,107 @@ fn parse_or(input: &str) -> IResult<&str, Expr> {
             ),
             char(')'),
         ),
-        Expr::Or,
+        Term::Or,
+    )(input)
+}
+
+// Parses a reference to a definition
+fn parse_ref(input: &str) -> IResult<&str, Term> {
+    map(
+        terminated(
+            preceded(char('$'), parse_name),
+            multispace0,
+        ),
+        |name: &str| Term::Ref(name.to_string()),
     )(input)
 }
 
-// Parses a string that is not a keyword
-fn parse_regex(input: &str) -> IResult<&str, Expr> {
+// Parses a string that is not a keyword. A line break ends the regular expression, so the
+// definitions can be written one per line.
+fn parse_regex(input: &str) -> IResult<&str, Term> {
     map(
         terminated(
-            take_while(|c: char| c != ')' && c != ','),
+            take_while(|c: char| c != ')' && c != ',' && c != '\n'),
             opt(multispace0),
         ),
-        |s: &str| Expr::Regex(Regex::new(s).expect("Invalid regex")),
+        |s: &str| Term::Regex(Regex::new(s).expect("Invalid regex")),
     )(input)
 }
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+    use super::*;
+
+    // Regular expressions that can appear as leaves: no ',' or ')', which end a leaf
+    fn regex_leaf() -> impl Strategy<Value = Expr> {
+        prop_oneof![
+            "[a-zA-Z0-9@_. ]{1,8}",
+            Just("^import".to_string()),
+            Just("\\.java$".to_string()),
+            Just("@Test\\b".to_string()),
+        ].prop_map(|s| Expr::Regex(Regex::new(&s).unwrap()))
+    }
+
+    fn expr() -> impl Strategy<Value = Expr> {
+        regex_leaf().prop_recursive(4, 32, 4, |inner| {
+            prop_oneof![
+                prop::collection::vec(inner.clone(), 1..4).prop_map(Expr::And),
+                prop::collection::vec(inner.clone(), 1..4).prop_map(Expr::Or),
+                inner.prop_map(|expr| Expr::Not(Box::new(expr))),
+            ]
+        })
+    }
+
+    #[test]
+    fn test_definitions() {
+        let input = "DEF junit = OR(@Test,import org.junit)\nDEF main = NOT($junit)\nAND($main,abc)";
+        let expression = parse(input).unwrap();
+        assert_eq!(expression.to_string(), "AND(NOT(OR(@Test,import org.junit)),abc)");
+    }
+
+    #[test]
+    fn test_shared_definitions() {
+        let expressions = parse_many(&["DEF test = Test\nAND($test,abc)", "OR($test,def)"]).unwrap();
+        assert_eq!(expressions[1].to_string(), "OR(Test,def)");
+    }
+
+    #[test]
+    fn test_undefined_reference() {
+        let error = parse("AND($junit,abc)").unwrap_err();
+        assert_eq!(error.to_string(), "Undefined expression $junit");
+    }
+
+    #[test]
+    fn test_cyclic_definition() {
+        let error = parse("DEF a = OR($b,abc)\nDEF b = NOT($a)\n$a").unwrap_err();
+        assert_eq!(error.to_string(), "Cyclic definition: $a -> $b -> $a");
+    }
+
+    #[test]
+    fn test_missing_expression() {
+        let error = parse("DEF a = @Test\n").unwrap_err();
+        assert_eq!(error.to_string(), "Missing expression after the definitions in \"DEF a = @Test\\n\"");
+        assert!(parse("").is_err());
+    }
+
+    #[test]
+    fn test_trailing_input() {
+        assert!(parse("AND(abc,def))").is_err());
+        assert!(parse("AND(abc,def)\n").is_ok());
+    }
+
+    #[test]
+    fn test_display() {
+        let expression = parse("AND(OR(abc,def),NOT(ghi))").unwrap();
+        assert_eq!(expression.to_string(), "AND(OR(abc,def),NOT(ghi))");
+    }
+
+    proptest! {
+        #[test]
+        fn test_display_round_trip(expression in expr()) {
+            let printed = expression.to_string();
+            prop_assert_eq!(parse(&printed).unwrap(), expression, "{}", printed);
+        }
+    }
+}
@@ -0,0 +1,186 @@
+// Identities of the authors and committers, merged by the .mailmap of the repository and by an
+// extra mailmap file, in the format of git's .mailmap:
+//
+// Proper Name <commit@email>
+// <proper@email> <commit@email>
+// Proper Name <proper@email> <commit@email>
+// Proper Name <proper@email> Commit Name <commit@email>
+
+use color_eyre::eyre::{eyre, Result};
+use git2::{Mailmap, Repository, Signature};
+
+/// A line of a mailmap file: the identity to use for the commits with the given email, and
+/// optionally name
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MailmapEntry {
+    pub real_name: Option<String>,
+    pub real_email: Option<String>,
+    pub replace_name: Option<String>,
+    pub replace_email: String,
+}
+
+/// Reads the entries of a mailmap file
+pub fn read_mailmap(file_path: &str) -> Result<Vec<MailmapEntry>> {
+    let content = std::fs::read_to_string(file_path)
+        .map_err(|e| eyre!("Failed to read the mailmap {}: {}", file_path, e))?;
+
+    let mut entries = Vec::new();
+    for (idx, line) in content.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or_default().trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let entry = parse_mailmap_line(line)
+            .ok_or_else(|| eyre!("Invalid mailmap entry in {}, line {}: {}", file_path, idx + 1, line))?;
+        entries.push(entry);
+    }
+
+    Ok(entries)
+}
+
+// Parses a line as names, each followed by an email in angle brackets
+fn parse_mailmap_line(line: &str) -> Option<MailmapEntry> {
+    let mut identities = Vec::new();
+    let mut rest = line;
+    while let Some(start) = rest.find('<') {
+        let end = start + rest[start..].find('>')?;
+        let name = rest[..start].trim();
+        identities.push(((!name.is_empty()).then(|| name.to_string()), rest[start + 1..end].to_string()));
+        rest = &rest[end + 1..];
+    }
+
+    if !rest.trim().is_empty() {
+        return None;
+    }
+
+    match identities.as_slice() {
+        [(real_name, replace_email)] => Some(MailmapEntry {
+            real_name: real_name.clone(),
+            real_email: None,
+            replace_name: None,
+            replace_email: replace_email.clone(),
+        }),
+        [(real_name, real_email), (replace_name, replace_email)] => Some(MailmapEntry {
+            real_name: real_name.clone(),
+            real_email: Some(real_email.clone()),
+            replace_name: replace_name.clone(),
+            replace_email: replace_email.clone(),
+        }),
+        _ => None,
+    }
+}
+
+/// Returns the mailmap of the repository, with the extra entries taking precedence
+pub fn load_mailmap(repo: &Repository, extra_entries: &[MailmapEntry]) -> Result<Mailmap> {
+    let mut mailmap = match repo.mailmap() {
+        Ok(mailmap) => mailmap,
+        Err(_) => Mailmap::new()?,
+    };
+
+    for entry in extra_entries {
+        mailmap.add_entry(
+            entry.real_name.as_deref(),
+            entry.real_email.as_deref(),
+            entry.replace_name.as_deref(),
+            &entry.replace_email,
+        )?;
+    }
+
+    Ok(mailmap)
+}
+
+/// Returns the content of every mailmap the repository mailmap is read from, as libgit2
+/// resolves them: the .mailmap of the working directory, or of HEAD in a bare repository, the
+/// blob of mailmap.blob and the file of mailmap.file. The missing ones are empty.
+pub fn repository_mailmap_sources(repo: &Repository) -> Result<String> {
+    let blob_content = |revision: &str| -> String {
+        repo.revparse_single(revision).ok()
+            .and_then(|object| object.peel_to_blob().ok())
+            .map(|blob| String::from_utf8_lossy(blob.content()).to_string())
+            .unwrap_or_default()
+    };
+
+    let mailmap = match repo.workdir() {
+        Some(workdir) => std::fs::read_to_string(workdir.join(".mailmap")).unwrap_or_default(),
+        None => blob_content("HEAD:.mailmap"),
+    };
+
+    let config = repo.config()?;
+    let mailmap_blob = config.get_string("mailmap.blob").ok()
+        .map(|revision| blob_content(&revision))
+        .unwrap_or_default();
+    let mailmap_file = config.get_path("mailmap.file").ok()
+        .and_then(|path| std::fs::read_to_string(path).ok())
+        .unwrap_or_default();
+
+    Ok(format!(".mailmap: {:?}, mailmap.blob: {:?}, mailmap.file: {:?}", mailmap, mailmap_blob, mailmap_file))
+}
+
+/// Formats the identity after resolving it with the mailmap, as "Name <email>"
+pub fn resolve_identity(mailmap: &Mailmap, signature: &Signature) -> String {
+    match mailmap.resolve_signature(signature) {
+        Ok(resolved) => format_signature(&resolved),
+        Err(_) => format_signature(signature),
+    }
+}
+
+fn format_signature(signature: &Signature) -> String {
+    format!("{} <{}>", signature.name().unwrap_or_default(), signature.email().unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_mailmap_line() {
+        let entry = parse_mailmap_line("Jane Doe <jane@example.com> jd <jd@old.com>").unwrap();
+        assert_eq!(entry, MailmapEntry {
+            real_name: Some("Jane Doe".to_string()),
+            real_email: Some("jane@example.com".to_string()),
+            replace_name: Some("jd".to_string()),
+            replace_email: "jd@old.com".to_string(),
+        });
+
+        let entry = parse_mailmap_line("<jane@example.com> <jd@old.com>").unwrap();
+        assert_eq!((entry.real_name, entry.real_email), (None, Some("jane@example.com".to_string())));
+
+        let entry = parse_mailmap_line("Jane Doe <jd@old.com>").unwrap();
+        assert_eq!((entry.real_name, entry.replace_email), (Some("Jane Doe".to_string()), "jd@old.com".to_string()));
+
+        assert_eq!(parse_mailmap_line("Jane Doe"), None);
+        assert_eq!(parse_mailmap_line("Jane <a> <b> <c>"), None);
+    }
+
+    #[test]
+    fn test_resolve_identity() {
+        let entry = parse_mailmap_line("Jane Doe <jane@example.com> <jd@old.com>").unwrap();
+        let mut mailmap = Mailmap::new().unwrap();
+        mailmap.add_entry(entry.real_name.as_deref(), entry.real_email.as_deref(), entry.replace_name.as_deref(), &entry.replace_email).unwrap();
+
+        let signature = Signature::now("jd", "jd@old.com").unwrap();
+        assert_eq!(resolve_identity(&mailmap, &signature), "Jane Doe <jane@example.com>");
+    }
+
+    #[test]
+    fn test_repository_mailmap_sources() {
+        let path = std::env::temp_dir().join(format!("identities_mailmap_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+        let repo = Repository::init(&path).unwrap();
+        let empty = repository_mailmap_sources(&repo).unwrap();
+
+        // The file of mailmap.file changes the sources, as the .mailmap does
+        let mailmap_file = path.join("extra.mailmap");
+        std::fs::write(&mailmap_file, "Jane Doe <jane@example.com> <jd@old.com>\n").unwrap();
+        repo.config().unwrap().set_str("mailmap.file", mailmap_file.to_str().unwrap()).unwrap();
+        let with_file = repository_mailmap_sources(&repo).unwrap();
+        assert_ne!(with_file, empty);
+        assert!(with_file.contains("jd@old.com"));
+
+        std::fs::write(path.join(".mailmap"), "Jane Doe <jane@new.com>\n").unwrap();
+        assert!(repository_mailmap_sources(&repo).unwrap().contains("jane@new.com"));
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+}
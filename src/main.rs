@@ -3,45 +3,240 @@ extern crate core;
 mod arguments;
 mod expression_parser;
 mod expression_interpreter;
+mod expression_optimiser;
 mod commits;
+mod decoding;
 mod analysis;
+mod blob_cache;
+mod commit_cache;
+mod detection;
+mod test_cases;
+mod pairing;
+mod coevolution;
+mod periods;
+mod tdd;
 mod serialization;
+mod tabular;
+mod database;
+mod identities;
+mod query;
+mod stats;
+mod snapshots;
 
 use clap::Parser;
-use color_eyre::eyre::Result;
+use color_eyre::eyre::{eyre, Result};
 use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
 use crate::commits::CommitSendSync;
 use rayon::prelude::*;
-use analysis::delete_duplicates;
-use crate::serialization::write_to_file;
+use analysis::{delete_duplicates, link_history, Classifier};
+use crate::serialization::{write_to_file, CommitData, JsonLinesWriter, OutputFormat, RepositoryMetadata, RunCounts, RunMetadata, NativeDateTimeWrapper, SCHEMA_VERSION};
+use crate::expression_optimiser::optimise;
+use crate::coevolution::co_evolution_report;
+use crate::tdd::tdd_report;
+use crate::tabular::{rows, write_csv, write_parquet};
+use crate::database::write_to_database;
+use crate::decoding::SkippedFiles;
+use crate::blob_cache::BlobCache;
+use crate::commit_cache::{cache_stats, clear_cache, CommitCache};
+use crate::arguments::{CacheAction, Command, FilterArguments};
+use crate::identities::{read_mailmap, repository_mailmap_sources};
+use crate::snapshots::Snapshot;
+use crate::query::{load_commits, merge, summarize, CommitFilter};
+use crate::stats::{author_of, format_table, release_of, release_tags, stats, write_stats_csv, StatsGroup};
+use std::sync::Arc;
+
+fn run_command(command: &Command) -> Result<()> {
+    match command {
+        Command::Cache { action: CacheAction::Stats { dir } } => {
+            for stats in cache_stats(dir)? {
+                println!("{}: {} commits, {} bytes", stats.hash, stats.commits, stats.bytes);
+                for line in stats.configuration.lines() {
+                    println!("    {}", line);
+                }
+            }
+        }
+        Command::Cache { action: CacheAction::Clear { dir } } => {
+            let commits = clear_cache(dir)?;
+            println!("Removed {} cached commits", commits);
+        }
+        Command::Merge { files, output, filter } => {
+            let commits = load_filtered(files, filter)?;
+
+            let mut writer = JsonLinesWriter::create(output, false, false, None)?;
+            writer.write_chunk(commits.into_iter().map(Some).enumerate().collect())?;
+            println!("Wrote {} commits", writer.finish(None)?);
+        }
+        Command::Summary { files, filter } => {
+            print!("{}", summarize(&load_filtered(files, filter)?));
+        }
+    }
+
+    Ok(())
+}
+
+// Loads and merges the output files, and selects the commits
+fn load_filtered(files: &[String], filter: &FilterArguments) -> Result<Vec<CommitData>> {
+    let mut loaded = Vec::new();
+    for file in files {
+        let file = load_commits(file)?;
+        if let Some(metadata) = &file.metadata {
+            println!(
+                "{}: {} at {}, {} version {}",
+                file.path, metadata.repository.path, metadata.repository.head, env!("CARGO_PKG_NAME"), metadata.tool_version,
+            );
+        }
+        loaded.push(file);
+    }
+
+    let filter = CommitFilter {
+        since: filter.since,
+        until: filter.until,
+        path: filter.path.as_deref().map(regex::Regex::new).transpose()?,
+        label: filter.label.clone(),
+    };
+    Ok(filter.apply(merge(loaded)))
+}
+
+// Number of commits analysed before their results are written, when streaming
+const STREAM_CHUNK_SIZE: usize = 256;
 
 fn main() -> Result<()>{
     color_eyre::install()?;
 
     // Parse arguments
     let args = arguments::Arguments::parse();
+    let start_time = chrono::Utc::now().naive_utc();
+
+    // Maintenance of the cache and reading of the output files
+    if let Some(command) = &args.command {
+        return run_command(command);
+    }
+
+    // Parse the regular expressions trees, the definitions are shared between all of them
+    let mut inputs = vec![args.regex_pattern.as_deref().unwrap()];
+    inputs.extend(args.evaluate_name.as_deref());
+    inputs.extend(args.label.iter().map(|(_, expr)| expr.as_str()));
+    inputs.extend(args.label_name.iter().map(|(_, expr)| expr.as_str()));
+    let mut exprs = expression_parser::parse_many(&inputs)?.into_iter();
+    let expr = exprs.next().unwrap();
+    let evaluate_name_expr = args.evaluate_name.as_ref().and_then(|_| exprs.next());
+
+    // Group the content and name expressions of each label into a classifier
+    let mut classifiers: Vec<Classifier> = Vec::new();
+    let labels = args.label.iter().map(|(label, _)| (label, true))
+        .chain(args.label_name.iter().map(|(label, _)| (label, false)));
+    for ((label, is_content), label_expr) in labels.zip(exprs) {
+        let position = classifiers.iter().position(|classifier| &classifier.label == label);
+        let classifier = match position {
+            Some(position) => &mut classifiers[position],
+            None => {
+                classifiers.push(Classifier { label: label.clone(), expr: None, name_expr: None });
+                classifiers.last_mut().unwrap()
+            }
+        };
+
+        let (slot, flag) = if is_content {
+            (&mut classifier.expr, "--label")
+        } else {
+            (&mut classifier.name_expr, "--label-name")
+        };
+        if slot.is_some() {
+            return Err(eyre!("The label {} is given more than once with {}", label, flag));
+        }
+        *slot = Some(label_expr);
+    }
+
+    // Show how the expressions were understood and exit
+    if args.print_expr {
+        println!("{}", optimise(expr));
+        if let Some(evaluate_name_expr) = evaluate_name_expr {
+            println!("{}", optimise(evaluate_name_expr));
+        }
+        for classifier in classifiers {
+            if let Some(label_expr) = classifier.expr {
+                println!("{}: {}", classifier.label, optimise(label_expr));
+            }
+            if let Some(label_name_expr) = classifier.name_expr {
+                println!("{} (name): {}", classifier.label, optimise(label_name_expr));
+            }
+        }
+        return Ok(());
+    }
+
+    // The reports, snapshots and statistics are written as a whole, never as JSON Lines
+    if !args.snapshot.is_empty() && args.format != OutputFormat::Json {
+        return Err(eyre!("--snapshot is only written as JSON"));
+    }
+    let report = args.co_evolution.is_some() || args.tdd_report;
+    if report && args.format != OutputFormat::Json {
+        return Err(eyre!("--co-evolution and --tdd-report are only written as JSON"));
+    }
+    if args.stats && !matches!(args.format, OutputFormat::Json | OutputFormat::Csv) {
+        return Err(eyre!("--stats is only written as JSON or CSV"));
+    }
+    // Duplicates and history links need every commit before anything is written
+    let stream = args.format == OutputFormat::Jsonl;
+    if stream && (args.delete_duplicates || args.link_tests_history) {
+        return Err(eyre!("--format jsonl cannot be used with --delete-duplicates or --link-tests-history"));
+    }
+    if args.ordered && !stream {
+        return Err(eyre!("--ordered is only used when the commits are written with --format jsonl"));
+    }
+    let commits_written = !report && !args.stats && args.snapshot.is_empty();
+    if args.append && !(stream || (commits_written && args.format == OutputFormat::Sqlite)) {
+        return Err(eyre!("--append is only used when the commits are written with --format jsonl or sqlite"));
+    }
 
     // Open repository
     let repository = git2::Repository::open(&args.path)?;
 
-    // Initialize the git repository walker
-    let walker = commits::get_commit_walker(
-        &repository,
-        &args.branch,
-        args.start_date,
-        args.end_date,
-        args.start_commit,
-    )?;
+    // Record how the results are produced, so they can be reproduced. The snapshots do not
+    // take a branch, the HEAD of the repository is recorded instead
+    let (branch, head) = if args.snapshot.is_empty() {
+        (args.branch.clone(), commits::find_branch_head(&repository, &args.branch)?)
+    } else {
+        let head = repository.head()?;
+        (head.shorthand().unwrap_or("HEAD").to_string(), head.peel_to_commit()?.id())
+    };
+    let mut metadata = RunMetadata {
+        schema_version: SCHEMA_VERSION,
+        tool_version: env!("CARGO_PKG_VERSION").to_string(),
+        repository: RepositoryMetadata {
+            path: std::fs::canonicalize(&args.path)?.to_string_lossy().to_string(),
+            branch,
+            head: head.to_string(),
+        },
+        configuration: serde_json::to_value(&args)?,
+        start_time: NativeDateTimeWrapper(start_time),
+        end_time: NativeDateTimeWrapper(start_time),
+        counts: RunCounts::default(),
+    };
 
-    // Parse the regular expressions tree
-    let expr = expression_parser::parse(args.regex_pattern.as_str())?;
+    // Initialize the git repository walker, or take the commits of the snapshots
+    let walker: Vec<git2::Commit> = if args.snapshot.is_empty() {
+        commits::get_commit_walker(
+            &repository,
+            &args.branch,
+            args.start_date,
+            args.end_date,
+            args.start_commit.clone(),
+            args.history_order,
+            // The reports and the ordered JSON Lines follow the history from the oldest commit
+            args.co_evolution.is_some() || args.tdd_report || (stream && args.ordered),
+        )?.collect()
+    } else {
+        args.snapshot.iter()
+            .map(|revision| repository.revparse_single(revision).and_then(|object| object.peel_to_commit()))
+            .collect::<Result<_, _>>()?
+    };
 
     println!("Starting analysis...");
     println!("Considering files with extensions: {:?}", args.extensions);
 
     // Wrap the commits in a SendSync wrapper so it can be used in parallel
-    let commits: Vec<CommitSendSync> = walker.map(|commit| CommitSendSync {
-        commit
+    let commits: Vec<CommitSendSync> = walker.into_iter().enumerate().map(|(position, commit)| CommitSendSync {
+        commit,
+        position,
     }).collect();
 
     // Obtain number of available cores
@@ -57,14 +252,22 @@ fn main() -> Result<()>{
     // Initialize Rayon with the number of cores
     rayon::ThreadPoolBuilder::new().num_threads(num_processes).build_global().unwrap();
 
-    // Split the commits into chunks
-    let chunk_size = (commits.len() as f32 / num_processes as f32).ceil() as usize;
+    // Split the commits into chunks, small ones when streaming so the results are written
+    // regularly
+    let mut chunk_size = (commits.len() as f32 / num_processes as f32).ceil().max(1.0) as usize;
+    if stream {
+        chunk_size = chunk_size.min(STREAM_CHUNK_SIZE);
+    }
     let chunks: Vec<Vec<CommitSendSync>> = commits.chunks(chunk_size).map(|chunk| chunk.to_vec()).collect();
 
-    // Create a progress bar for each chunk
+    // Create a progress bar for each chunk, or a single one for the chunks of the stream
+    let stream_progress = stream.then(|| m.add(ProgressBar::new(commits.len() as u64)));
     let mut chunks_and_progress= Vec::new();
     for chunk in chunks.into_iter() {
-        let progress_bar = m.add(ProgressBar::new(chunk.len() as u64));
+        let progress_bar = match &stream_progress {
+            Some(progress_bar) => progress_bar.clone(),
+            None => m.add(ProgressBar::new(chunk.len() as u64)),
+        };
         progress_bar.set_style(sty.clone());
         chunks_and_progress.push((chunk, progress_bar));
     }
@@ -72,29 +275,143 @@ fn main() -> Result<()>{
     // Mutex output vector, so it can be used in parallel
     let files = std::sync::Mutex::new(Vec::new());
 
+    // The cached classification of a blob depends on the content expressions and on how the
+    // content is read
+    let mut fingerprint = vec![expr.to_string()];
+    for classifier in &classifiers {
+        if let Some(label_expr) = &classifier.expr {
+            fingerprint.push(format!("{}: {}", classifier.label, label_expr));
+        }
+    }
+    fingerprint.push(format!("encoding: {:?}, match prefix: {:?}", args.encoding, args.match_prefix));
+    let fingerprint = fingerprint.join("\n");
+
+    // The results of a commit also depend on the name expressions and the other options
+    let mut configuration = vec![fingerprint.clone(), format!("extensions: {:?}", args.extensions)];
+    if let Some(evaluate_name_expr) = &evaluate_name_expr {
+        configuration.push(format!("name: {}", evaluate_name_expr));
+    }
+    for classifier in &classifiers {
+        if let Some(label_name_expr) = &classifier.name_expr {
+            configuration.push(format!("{} (name): {}", classifier.label, label_name_expr));
+        }
+    }
+    // The statistics count the non-test files too, and the commits without test files
+    let include_non_tests = args.save_non_tests || args.stats;
+    configuration.push(format!(
+        "full path: {}, match target: {:?}, explain: {}, non tests: {}, non-test commits: {}, max blob size: {:?}",
+        args.full_path, args.match_target, args.explain, include_non_tests, args.stats, args.max_blob_size,
+    ));
+    configuration.push(format!(
+        "frameworks: {}, count tests: {}, track tests: {}, link tests: {}",
+        args.detect_frameworks, args.count_tests, args.track_tests, args.link_tests,
+    ));
+    // The identities depend on the mailmaps of the repository and on the extra mailmap
+    let mailmap_entries = args.mailmap.as_deref().map(read_mailmap).transpose()?.unwrap_or_default();
+    configuration.push(format!(
+        "mailmap: {}, extra mailmap: {:?}", repository_mailmap_sources(&repository)?, mailmap_entries,
+    ));
+    let commit_cache = args.cache_dir.as_ref()
+        .map(|cache_dir| CommitCache::open(cache_dir, &configuration.join("\n")))
+        .transpose()?;
+
+    let blob_cache = match &args.blob_cache {
+        Some(path) => BlobCache::load(path, fingerprint)?,
+        None => BlobCache::new(fingerprint),
+    };
+    let blob_cache = Arc::new(blob_cache);
+
+    // Compile the expressions once, before the analysers are created
+    let compiled = analysis::CompiledExpressions::compile(&expr, evaluate_name_expr.as_ref(), &classifiers)?;
+
     // Initialize the analyser options
-    let evaluate_name_expr = args.evaluate_name.map(|expr| expression_parser::parse(expr.as_str()).unwrap());
     let analyser_opts = analysis::AnalyserOptions {
         evaluate_name: evaluate_name_expr,
-        include_non_tests: args.save_non_tests,
+        include_non_tests,
+        keep_non_test_commits: args.stats,
         full_path: args.full_path,
+        match_target: args.match_target,
+        explain: args.explain,
+        classifiers,
+        detect_frameworks: args.detect_frameworks,
+        count_tests: args.count_tests,
+        track_tests: args.track_tests,
+        link_tests: args.link_tests,
+        encoding: args.encoding,
+        max_blob_size: args.max_blob_size,
+        match_prefix: args.match_prefix,
+        blob_cache: blob_cache.clone(),
+        mailmap_entries,
+    };
+
+    // Classified trees of the snapshots, with the position of their revision
+    let snapshots = std::sync::Mutex::new(Vec::new());
+
+    // Test and production files changed by every commit, for the co-evolution report
+    let commit_changes = std::sync::Mutex::new(Vec::new());
+
+    // Files skipped by every analyser, for the run summary
+    let skipped = std::sync::Mutex::new(SkippedFiles::default());
+
+    // Writer of the commits as they are analysed
+    let json_lines = if stream {
+        let output = args.output.as_deref().unwrap();
+        Some(std::sync::Mutex::new(JsonLinesWriter::create(output, args.append, args.ordered, Some(&metadata))?))
+    } else {
+        None
     };
 
-    // Analyse each chunk in parallel
-    chunks_and_progress.par_iter().for_each(|(chunk, pb)| {
+    // Analyse each chunk in parallel. The chunks are taken in order, so the ordered stream
+    // holds back few results
+    chunks_and_progress.iter().par_bridge().try_for_each(|(chunk, pb)| -> Result<()> {
         // Open a repository and clone the other arguments to create an analyser
-        let repo = git2::Repository::open(&args.path).unwrap();
-        let analyser = analysis::Analyser::new(repo, args.extensions.clone(), expr.clone(), analyser_opts.clone());
+        let repo = git2::Repository::open(&args.path)?;
+        let analyser = analysis::Analyser::new(&repo, args.extensions.clone(), expr.clone(), compiled.clone(), analyser_opts.clone())?;
 
-        // Store the results in a temporary vector
+        // Store the results in a temporary vector, with the position of every commit when
+        // streaming
         let mut commit_data = Vec::new();
+        let mut positioned_commit_data = Vec::new();
+        let mut chunk_changes = Vec::new();
+        let mut chunk_snapshots = Vec::new();
 
         // Analyse each commit in the chunk
         for commit in chunk {
-            let commit_datum = analyser.process_commit(&commit.commit).unwrap();
+            // The snapshots classify the whole tree of the commit instead of its changes
+            if !args.snapshot.is_empty() {
+                let snapshot = analyser.process_tree(&commit.commit, &args.snapshot[commit.position])?;
+                chunk_snapshots.push((commit.position, snapshot));
+                pb.inc(1);
+                continue;
+            }
+
+            // The co-evolution and TDD reports need every change of the files instead
+            if args.co_evolution.is_some() || args.tdd_report {
+                chunk_changes.push(analyser.classify_changes(&commit.commit, commit.position)?);
+                pb.inc(1);
+                continue;
+            }
+
+            // Reuse the analysis of a previous run with the same configuration
+            let cached = commit_cache.as_ref().and_then(|cache| cache.get(commit.commit.id()));
+            let commit_datum = match cached {
+                Some(commit_datum) => commit_datum,
+                None => {
+                    let commit_datum = analyser.process_commit(&commit.commit)?;
+                    if let Some(cache) = &commit_cache {
+                        // The cache is only an optimisation, a failed write must not stop the run
+                        if let Err(error) = cache.put(commit.commit.id(), &commit_datum) {
+                            pb.println(format!("Could not cache the commit {}: {}", commit.commit.id(), error));
+                        }
+                    }
+                    commit_datum
+                }
+            };
 
             // If the commit has relevant data, add it to the temporary vector
-            if let Some(commit_datum) = commit_datum {
+            if stream {
+                positioned_commit_data.push((commit.position, commit_datum));
+            } else if let Some(commit_datum) = commit_datum {
                 commit_data.push(commit_datum);
             }
 
@@ -102,16 +419,93 @@ fn main() -> Result<()>{
             pb.inc(1);
         }
 
-        // Lock and append the temporary vector to the output vector
-        files.lock().unwrap().extend(commit_data);
-    });
+        // Lock and append the temporary vector to the output vector, or write it
+        match &json_lines {
+            Some(json_lines) => json_lines.lock().unwrap().write_chunk(positioned_commit_data)?,
+            None => files.lock().unwrap().extend(commit_data),
+        }
+        commit_changes.lock().unwrap().extend(chunk_changes);
+        snapshots.lock().unwrap().extend(chunk_snapshots);
+        skipped.lock().unwrap().add(&analyser.skipped_files());
+        Ok(())
+    })?;
 
     m.clear()?;
 
+    // Summary of the files that could not be analysed
+    let skipped = skipped.into_inner().unwrap();
+    metadata.counts.commits = commits.len();
+    metadata.counts.skipped_files = skipped.clone();
+    println!(
+        "Skipped files: {} (missing: {}, binary: {}, undecodable: {}, too large: {})",
+        skipped.total(), skipped.missing, skipped.binary, skipped.undecodable, skipped.too_large,
+    );
+    println!("Blobs classified from the cache: {}", blob_cache.hits());
+
+    // Keep the classification of the blobs for the next runs
+    if let Some(path) = &args.blob_cache {
+        println!("Saving {} blobs to the cache...", blob_cache.len());
+        blob_cache.save(path)?;
+    }
+
+    // Write the snapshots in the order of the revisions instead of the commits
+    if !args.snapshot.is_empty() {
+        let mut snapshots = snapshots.into_inner().unwrap();
+        snapshots.sort_by_key(|(position, _)| *position);
+        let snapshots: Vec<Snapshot> = snapshots.into_iter().map(|(_, snapshot)| snapshot).collect();
+        print!("{}", snapshots::format_table(&snapshots));
+
+        println!("Writing output to file...");
+        metadata.finish(snapshots.len());
+        write_to_file(&snapshots, &metadata, args.output.unwrap().as_str())?;
+        println!("Done!");
+        return Ok(());
+    }
+
+    // Write the co-evolution or TDD report instead of the commits
+    let mut commit_changes = commit_changes.into_inner().unwrap();
+    commit_changes.sort_by_key(|changes| changes.position);
+
+    if let Some(window) = args.co_evolution {
+        println!("Building co-evolution report...");
+        let report = co_evolution_report(&commit_changes, window);
+
+        println!("Writing output to file...");
+        metadata.finish(report.len());
+        write_to_file(&report, &metadata, args.output.unwrap().as_str())?;
+        println!("Done!");
+        return Ok(());
+    }
+
+    if args.tdd_report {
+        println!("Building TDD report...");
+        let report = tdd_report(&commit_changes, args.period);
+
+        println!("Writing output to file...");
+        metadata.finish(1);
+        write_to_file(&report, &metadata, args.output.unwrap().as_str())?;
+        println!("Done!");
+        return Ok(());
+    }
+
+    // The commits were already written
+    if let Some(json_lines) = json_lines {
+        let written = json_lines.into_inner().unwrap().finish(Some(&mut metadata))?;
+        println!("Wrote {} commits", written);
+        println!("Done!");
+        return Ok(());
+    }
+
     // Sort the commits by date
     println!("Sorting commits...");
     let mut files = files.into_inner().unwrap();
-    files.sort_by(|a, b| a.date.0.cmp(&b.date.0));
+    files.sort_by_key(|a| a.date.0);
+
+    // Pair the test files with the production files of the whole history
+    if args.link_tests_history {
+        println!("Linking tests across history...");
+        link_history(&mut files);
+    }
 
     // Delete duplicates
     let files_to_write = if args.delete_duplicates {
@@ -121,9 +515,42 @@ fn main() -> Result<()>{
         files
     };
 
+    // Write the statistics of the commits instead of the commits
+    if args.stats {
+        println!("Building statistics...");
+        let rows = match args.stats_by {
+            StatsGroup::Period => stats(&files_to_write, |commit_data| args.period.label(&commit_data.date.0)),
+            StatsGroup::Release => {
+                let releases = release_tags(&repository)?;
+                stats(&files_to_write, |commit_data| release_of(&releases, commit_data))
+            }
+            StatsGroup::Author => stats(&files_to_write, author_of),
+        };
+        print!("{}", format_table(&rows));
+
+        println!("Writing output to file...");
+        let output = args.output.unwrap();
+        metadata.finish(rows.len());
+        if args.format == OutputFormat::Csv {
+            write_stats_csv(&rows, &metadata, &output)?;
+        } else {
+            write_to_file(&rows, &metadata, &output)?;
+        }
+        println!("Done!");
+        return Ok(());
+    }
+
     // Write the output to a file
     println!("Writing output to file...");
-    write_to_file(&files_to_write, args.output.as_str())?;
+    let output = args.output.unwrap();
+    metadata.finish(files_to_write.len());
+    match args.format {
+        OutputFormat::Json => write_to_file(&files_to_write, &metadata, &output)?,
+        OutputFormat::Jsonl => unreachable!("the JSON Lines are written during the analysis"),
+        OutputFormat::Csv => write_csv(&rows(&files_to_write), &metadata, &output)?,
+        OutputFormat::Parquet => write_parquet(&rows(&files_to_write), &metadata, &output)?,
+        OutputFormat::Sqlite => write_to_database(&files_to_write, &metadata, &output, args.append)?,
+    }
     println!("Done!");
 
     Ok(())
@@ -0,0 +1,185 @@
+// Heuristics to pair test files with the production files they exercise, by file name
+// conventions (FooTest.java - Foo.java, test_foo.py - foo.py, foo.test.ts - foo.ts), by the
+// imports of the test file and by the classes it uses from its own package.
+
+use lazy_static::lazy_static;
+use regex::Regex;
+use std::path::Path;
+use crate::detection::language;
+
+lazy_static! {
+    static ref JAVA_IMPORT: Regex = Regex::new(r"(?m)^\s*import\s+(static\s+)?([\w.]+)\s*;?").unwrap();
+    static ref JAVA_PACKAGE: Regex = Regex::new(r"(?m)^\s*package\s+([\w.]+)").unwrap();
+    static ref CLASS_NAME: Regex = Regex::new(r"\b[A-Z]\w*\b").unwrap();
+    static ref PYTHON_IMPORT: Regex = Regex::new(r"(?m)^\s*(from\s+([\w.]+)\s+import|import\s+([\w.]+))").unwrap();
+}
+
+// Splits a path into its file stem and extension
+fn stem_and_extension(file: &str) -> Option<(&str, &str)> {
+    let path = Path::new(file);
+    Some((path.file_stem()?.to_str()?, path.extension()?.to_str()?))
+}
+
+/// Returns the file stems the production file of a test file could have
+pub fn production_stems(test_file: &str) -> Vec<String> {
+    let Some((stem, _)) = stem_and_extension(test_file) else {
+        return Vec::new();
+    };
+
+    let mut stems = Vec::new();
+
+    // foo.test.ts, foo.spec.js
+    for infix in [".test", ".spec"] {
+        if let Some(stripped) = stem.strip_suffix(infix) {
+            stems.push(stripped.to_string());
+        }
+    }
+
+    // FooTest, FooTests, FooIT, FooSpec, foo_test
+    for suffix in ["Tests", "Test", "IT", "Spec", "_test", "_spec"] {
+        if let Some(stripped) = stem.strip_suffix(suffix) {
+            stems.push(stripped.to_string());
+        }
+    }
+
+    // TestFoo, test_foo
+    for prefix in ["test_", "Test"] {
+        if let Some(stripped) = stem.strip_prefix(prefix) {
+            stems.push(stripped.to_string());
+        }
+    }
+
+    stems.retain(|stem| !stem.is_empty());
+    stems
+}
+
+/// Returns the path suffixes of the modules imported by a test file, such as
+/// com/example/Foo.java for `import com.example.Foo;`. The classes of its own package are used
+/// without imports, so the classes named in a test file of the package com.example give
+/// com/example/Name.java too. The relative imports of Python are resolved from the directory
+/// of the test file.
+pub fn imported_paths(test_file: &str, content: &str) -> Vec<String> {
+    let Some((_, extension)) = stem_and_extension(test_file) else {
+        return Vec::new();
+    };
+
+    match language(extension) {
+        Some("Java") | Some("Kotlin") | Some("Groovy") | Some("Scala") => {
+            let mut paths: Vec<String> = JAVA_IMPORT.captures_iter(content)
+                .map(|capture| format!("{}.{}", capture[2].replace('.', "/"), extension))
+                .collect();
+
+            // The classes named outside the package and import declarations, and not imported
+            if let Some(package) = JAVA_PACKAGE.captures(content) {
+                let package = package[1].replace('.', "/");
+                let body = JAVA_IMPORT.replace_all(content, "");
+                let body = JAVA_PACKAGE.replace_all(&body, "");
+                let mut class_names: Vec<&str> = CLASS_NAME.find_iter(&body)
+                    .map(|name| name.as_str())
+                    .filter(|name| !paths.iter().any(|path| path.ends_with(&format!("/{}.{}", name, extension))))
+                    .collect();
+                class_names.sort_unstable();
+                class_names.dedup();
+                let package_paths: Vec<String> = class_names.into_iter().map(|name| format!("{}/{}.{}", package, name, extension)).collect();
+                paths.extend(package_paths);
+            }
+            paths
+        }
+        Some("Python") => PYTHON_IMPORT.captures_iter(content)
+            .filter_map(|capture| capture.get(2).or_else(|| capture.get(3)))
+            .map(|module| python_module_path(test_file, module.as_str()))
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+// Path of a Python module, resolving the leading dots of a relative import from the directory
+// of the importing file: .foo is a sibling, ..foo is in the parent directory
+fn python_module_path(file: &str, module: &str) -> String {
+    let relative_module = module.trim_start_matches('.');
+    let level = module.len() - relative_module.len();
+    let module_path = format!("{}.py", relative_module.replace('.', "/"));
+    if level == 0 {
+        return module_path;
+    }
+
+    let mut directory = Path::new(file).parent();
+    for _ in 1..level {
+        directory = directory.and_then(Path::parent);
+    }
+    match directory.and_then(Path::to_str).filter(|directory| !directory.is_empty()) {
+        Some(directory) => format!("{}/{}", directory, module_path),
+        None => module_path,
+    }
+}
+
+/// Whether the production file is likely exercised by the test file, by name or by one
+/// of the paths imported by the test file
+pub fn is_pair(test_file: &str, production_file: &str, imported_paths: &[String]) -> bool {
+    let (Some((_, test_extension)), Some((stem, extension))) = (stem_and_extension(test_file), stem_and_extension(production_file)) else {
+        return false;
+    };
+
+    // The files must be of the same language
+    let same_language = extension == test_extension
+        || language(extension).is_some_and(|language| Some(language) == self::language(test_extension));
+    if !same_language {
+        return false;
+    }
+
+    if production_stems(test_file).iter().any(|candidate| candidate == stem) {
+        return true;
+    }
+
+    // Compare the imported paths with as many components as the production file has
+    imported_paths.iter().any(|imported| {
+        imported == production_file
+            || imported.ends_with(&format!("/{}", production_file))
+            || production_file.ends_with(&format!("/{}", imported))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pair_by_name() {
+        assert!(is_pair("src/test/FooTest.java", "src/main/Foo.java", &[]));
+        assert!(is_pair("FooIT.java", "Foo.java", &[]));
+        assert!(is_pair("tests/test_foo.py", "foo/foo.py", &[]));
+        assert!(is_pair("foo.test.ts", "foo.tsx", &[]));
+        assert!(is_pair("foo_test.go", "foo.go", &[]));
+        assert!(!is_pair("FooTest.java", "Bar.java", &[]));
+        assert!(!is_pair("FooTest.java", "Foo.py", &[]));
+    }
+
+    #[test]
+    fn test_pair_by_import() {
+        let content = "package com.example.test;\nimport com.example.service.Bar;\nimport static org.junit.Assert.assertEquals;";
+        let imported = imported_paths("FooTest.java", content);
+        assert_eq!(imported, vec!["com/example/service/Bar.java", "org/junit/Assert/assertEquals.java"]);
+
+        assert!(is_pair("FooTest.java", "src/main/java/com/example/service/Bar.java", &imported));
+        assert!(is_pair("FooTest.java", "Bar.java", &imported));
+        assert!(!is_pair("FooTest.java", "src/main/java/com/other/Bar.java", &imported));
+
+        let imported = imported_paths("test_foo.py", "from app.models import User\nimport app.utils");
+        assert_eq!(imported, vec!["app/models.py", "app/utils.py"]);
+        assert!(is_pair("test_foo.py", "src/app/models.py", &imported));
+    }
+
+    #[test]
+    fn test_pair_by_package() {
+        let content = "package com.example.service;\n\nclass FooTest {\n    Bar bar = new Bar();\n}";
+        let imported = imported_paths("src/test/java/com/example/service/FooTest.java", content);
+        assert!(is_pair("FooTest.java", "src/main/java/com/example/service/Bar.java", &imported));
+        assert!(!is_pair("FooTest.java", "src/main/java/com/example/other/Bar.java", &imported));
+        assert!(!is_pair("FooTest.java", "src/main/java/com/example/service/Baz.java", &imported));
+
+        let imported = imported_paths("app/tests/test_views.py", "from .helpers import build\nfrom ..models import User");
+        assert_eq!(imported, vec!["app/tests/helpers.py", "app/models.py"]);
+        assert!(is_pair("app/tests/test_views.py", "app/models.py", &imported));
+        assert!(!is_pair("app/tests/test_views.py", "other/models.py", &imported));
+    }
+}
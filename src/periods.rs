@@ -0,0 +1,29 @@
+// Time periods used to group results in the reports.
+
+use chrono::{Datelike, NaiveDateTime};
+use serde::Serialize;
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum Period {
+    Day,
+    Week,
+    Month,
+    Year,
+}
+
+impl Period {
+    /// Returns the label of the period the date falls in, such as 2023-05-14, 2023-W19,
+    /// 2023-05 or 2023. Labels sort in chronological order.
+    pub fn label(&self, date: &NaiveDateTime) -> String {
+        match self {
+            Period::Day => date.format("%Y-%m-%d").to_string(),
+            Period::Week => {
+                let week = date.iso_week();
+                format!("{}-W{:02}", week.year(), week.week())
+            }
+            Period::Month => date.format("%Y-%m").to_string(),
+            Period::Year => date.format("%Y").to_string(),
+        }
+    }
+}
@@ -0,0 +1,312 @@
+// Reading of the output files back: loading, merging and filtering the commits, and
+// summarising them.
+
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use chrono::NaiveDate;
+use color_eyre::eyre::{eyre, Result};
+use regex::Regex;
+use crate::serialization::{CommitData, MetadataLine, Output, RunMetadata};
+
+// Start of the metadata lines of JSON Lines, as serde_json writes them without spaces
+const METADATA_LINE_START: &str = "{\"metadata\":";
+
+/// Commits of an output file, with the metadata of the run if the file has it
+pub struct LoadedFile {
+    pub path: String,
+    pub metadata: Option<RunMetadata>,
+    pub commits: Vec<CommitData>,
+}
+
+/// Loads the commits of a JSON output file, with or without the metadata, or of a JSON
+/// Lines output file, with the metadata of its first run, finished if the run finished
+pub fn load_commits(path: &str) -> Result<LoadedFile> {
+    let content = std::fs::read_to_string(path)
+        .map_err(|e| eyre!("Failed to read {}: {}", path, e))?;
+    let error = |e: serde_json::Error| eyre!("Failed to read {}: {}", path, e);
+
+    let mut loaded = LoadedFile {
+        path: path.to_string(),
+        metadata: None,
+        commits: Vec::new(),
+    };
+
+    // A bare array of commits, as written before the metadata was added
+    if content.trim_start().starts_with('[') {
+        loaded.commits = serde_json::from_str(&content).map_err(error)?;
+        return Ok(loaded);
+    }
+
+    // The first value tells an output with metadata from the first line of JSON Lines, which
+    // has the metadata but not the data
+    let first = serde_json::Deserializer::from_str(&content).into_iter::<serde_json::Value>().next();
+    if first.is_some_and(|first| first.is_ok_and(|first| first.get("metadata").is_some() && first.get("data").is_some())) {
+        let output: Output<CommitData> = serde_json::from_str(&content).map_err(error)?;
+        loaded.metadata = Some(output.metadata);
+        loaded.commits = output.data;
+        return Ok(loaded);
+    }
+
+    // Metadata lines read, to keep the finished line of the first run only
+    let mut metadata_lines = 0;
+    for (idx, line) in content.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let line_error = |e: serde_json::Error| eyre!("Failed to read {}, line {}: {}", path, idx + 1, e);
+
+        // The metadata lines are written before and after the commits of each run
+        if line.starts_with(METADATA_LINE_START) {
+            let metadata_line: MetadataLine = serde_json::from_str(line).map_err(line_error)?;
+            let first_run = metadata_lines == 0 || (metadata_lines == 1 && metadata_line.finished);
+            if first_run {
+                loaded.metadata = Some(metadata_line.metadata);
+            }
+            metadata_lines += 1;
+            continue;
+        }
+
+        let commit_data = serde_json::from_str(line).map_err(line_error)?;
+        loaded.commits.push(commit_data);
+    }
+
+    Ok(loaded)
+}
+
+/// Merges the commits of several files, keeping the first occurrence of each commit, sorted
+/// by date
+pub fn merge(files: Vec<LoadedFile>) -> Vec<CommitData> {
+    let mut seen = HashSet::new();
+    let mut commits: Vec<CommitData> = files.into_iter()
+        .flat_map(|file| file.commits)
+        .filter(|commit_data| seen.insert(commit_data.commit.clone()))
+        .collect();
+
+    commits.sort_by_key(|commit_data| commit_data.date.0);
+    commits
+}
+
+/// Selection of the commits, and of their files by path and label
+#[derive(Debug, Clone, Default)]
+pub struct CommitFilter {
+    pub since: Option<NaiveDate>,
+    pub until: Option<NaiveDate>,
+    pub path: Option<Regex>,
+    pub label: Option<String>,
+}
+
+impl CommitFilter {
+    /// Keeps the commits in the date range, and the files that match the path and have the
+    /// label. The commits left without files are dropped.
+    pub fn apply(&self, commits: Vec<CommitData>) -> Vec<CommitData> {
+        commits.into_iter()
+            .filter(|commit_data| {
+                let date = commit_data.date.0.date();
+                self.since.is_none_or(|since| date >= since) && self.until.is_none_or(|until| date <= until)
+            })
+            .filter_map(|commit_data| self.filter_files(commit_data))
+            .collect()
+    }
+
+    fn filter_files(&self, mut commit_data: CommitData) -> Option<CommitData> {
+        if self.path.is_none() && self.label.is_none() {
+            return Some(commit_data);
+        }
+
+        let labelled: Option<HashSet<String>> = self.label.as_ref().map(|label| {
+            commit_data.labels.iter()
+                .filter_map(|labels| labels.get(label))
+                .flatten()
+                .cloned()
+                .collect()
+        });
+        let keep = |file: &String| {
+            self.path.as_ref().is_none_or(|path| path.is_match(file))
+                && labelled.as_ref().is_none_or(|labelled| labelled.contains(file))
+        };
+
+        commit_data.test_files.retain(|entry| keep(&entry.file));
+        if let Some(non_test_files) = &mut commit_data.non_test_files {
+            non_test_files.retain(|entry| keep(&entry.file));
+        }
+        if let Some(tracked_test_files) = &mut commit_data.tracked_test_files {
+            tracked_test_files.retain(|entry| keep(&entry.file));
+        }
+        if let Some(explanations) = &mut commit_data.explanations {
+            explanations.retain(|explanation| keep(&explanation.file));
+        }
+        if let Some(test_cases) = &mut commit_data.test_cases {
+            *test_cases = commit_data.test_files.iter().filter_map(|entry| entry.details.test_cases).sum();
+        }
+        if let Some(labels) = &mut commit_data.labels {
+            for files in labels.values_mut() {
+                files.retain(keep);
+            }
+            labels.retain(|_, files| !files.is_empty());
+        }
+
+        let has_files = !commit_data.test_files.is_empty()
+            || commit_data.non_test_files.as_ref().is_some_and(|files| !files.is_empty())
+            || commit_data.tracked_test_files.as_ref().is_some_and(|files| !files.is_empty())
+            || commit_data.labels.as_ref().is_some_and(|labels| !labels.is_empty());
+        has_files.then_some(commit_data)
+    }
+}
+
+/// Totals of a set of commits
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Summary {
+    pub commits: usize,
+    pub first_date: Option<NaiveDate>,
+    pub last_date: Option<NaiveDate>,
+    pub test_files: usize,
+    pub non_test_files: usize,
+    pub test_cases: Option<usize>,
+    /// Files with each label
+    pub labels: BTreeMap<String, usize>,
+}
+
+pub fn summarize(commits: &[CommitData]) -> Summary {
+    let mut summary = Summary {
+        commits: commits.len(),
+        first_date: commits.iter().map(|commit_data| commit_data.date.0.date()).min(),
+        last_date: commits.iter().map(|commit_data| commit_data.date.0.date()).max(),
+        ..Default::default()
+    };
+
+    for commit_data in commits {
+        summary.test_files += commit_data.test_files.len();
+        summary.non_test_files += commit_data.non_test_files.as_ref().map_or(0, |files| files.len());
+        if let Some(test_cases) = commit_data.test_cases {
+            *summary.test_cases.get_or_insert(0) += test_cases;
+        }
+        for (label, files) in commit_data.labels.iter().flatten() {
+            *summary.labels.entry(label.clone()).or_default() += files.len();
+        }
+    }
+
+    summary
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Commits: {}", self.commits)?;
+        if let (Some(first_date), Some(last_date)) = (self.first_date, self.last_date) {
+            write!(f, " (from {} to {})", first_date, last_date)?;
+        }
+        writeln!(f)?;
+        writeln!(f, "Test files: {}", self.test_files)?;
+        writeln!(f, "Non-test files: {}", self.non_test_files)?;
+        if let Some(test_cases) = self.test_cases {
+            writeln!(f, "Test cases: {}", test_cases)?;
+        }
+        for (label, files) in &self.labels {
+            writeln!(f, "Label {}: {} files", label, files)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDateTime;
+    use crate::serialization::NativeDateTimeWrapper;
+
+    fn commit_data(commit: &str, date: &str, test_files: &[&str], mock_files: &[&str]) -> CommitData {
+        let mut labels = BTreeMap::new();
+        labels.insert("mock".to_string(), mock_files.iter().map(|file| file.to_string()).collect());
+
+        CommitData {
+            date: NativeDateTimeWrapper(NaiveDateTime::parse_from_str(date, "%Y-%m-%d %H:%M:%S").unwrap()),
+            labels: Some(labels),
+            ..CommitData::with_test_files(commit, test_files)
+        }
+    }
+
+    #[test]
+    fn test_merge_and_filter() {
+        let first = LoadedFile {
+            path: "first.json".to_string(),
+            metadata: None,
+            commits: vec![commit_data("b", "2023-02-01 00:00:00", &["src/FooTest.java"], &[])],
+        };
+        let second = LoadedFile {
+            path: "second.json".to_string(),
+            metadata: None,
+            commits: vec![
+                commit_data("a", "2023-01-01 00:00:00", &["lib/BarTest.java", "src/BazTest.java"], &["src/BazTest.java"]),
+                commit_data("b", "2023-02-01 00:00:00", &["src/FooTest.java"], &[]),
+            ],
+        };
+
+        let commits = merge(vec![first, second]);
+        assert_eq!(commits.iter().map(|commit_data| commit_data.commit.as_str()).collect::<Vec<_>>(), vec!["a", "b"]);
+
+        let filter = CommitFilter {
+            until: NaiveDate::from_ymd_opt(2023, 1, 31),
+            path: Some(Regex::new("^src/").unwrap()),
+            ..Default::default()
+        };
+        let filtered = filter.apply(commits.clone());
+        assert_eq!(filtered.len(), 1);
+        assert_eq!(filtered[0].test_files[0].file, "src/BazTest.java");
+
+        let filter = CommitFilter { label: Some("mock".to_string()), ..Default::default() };
+        let summary = summarize(&filter.apply(commits));
+        assert_eq!(summary.commits, 1);
+        assert_eq!(summary.test_files, 1);
+        assert_eq!(summary.labels["mock"], 1);
+    }
+
+    #[test]
+    fn test_load_reports_errors() {
+        let path = std::env::temp_dir().join(format!("malformed_{}.jsonl", std::process::id()));
+        let path = path.to_str().unwrap();
+        std::fs::write(path, "{\"commit\":\"a\",\"date\":\"2023-01-01 00:00:00 UTC\",\"size\":1,\"test_files\":[]}\n{\"commit\":\"b\",\"date\":\"yesterday\",\"size\":1,\"test_files\":[]}\n").unwrap();
+
+        let error = load_commits(path).err().unwrap().to_string();
+        assert!(error.contains("line 2"), "{}", error);
+        assert!(error.contains("Invalid date"), "{}", error);
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_load_json_lines_with_metadata() {
+        let path = std::env::temp_dir().join(format!("metadata_{}.jsonl", std::process::id()));
+        let path = path.to_str().unwrap();
+        // Written by hand, as serde_json sorts the keys of the objects it builds
+        let metadata = |head: &str, results: usize, finished: bool| format!("{{\"metadata\":{},\"finished\":{}}}", serde_json::json!({
+            "schema_version": 2,
+            "tool_version": "0.1.0",
+            "repository": {"path": "/repo", "branch": "master", "head": head},
+            "configuration": {},
+            "start_time": "2023-01-01 00:00:00 UTC",
+            "end_time": "2023-01-01 00:00:00 UTC",
+            "counts": {"commits": 0, "results": results, "skipped_files": {"missing": 0, "binary": 0, "undecodable": 0, "too_large": 0}},
+        }), finished);
+        let commit = |commit: &str| format!("{{\"commit\":\"{}\",\"date\":\"2023-01-01 00:00:00 UTC\",\"size\":1,\"test_files\":[]}}", commit);
+        let load = |lines: &[String]| {
+            std::fs::write(path, lines.join("\n")).unwrap();
+            let loaded = load_commits(path).unwrap();
+            let metadata = loaded.metadata.map(|metadata| (metadata.repository.head, metadata.counts.results));
+            (metadata, loaded.commits.into_iter().map(|commit_data| commit_data.commit).collect::<Vec<_>>())
+        };
+
+        // Two runs appended to the same file, the first one finished
+        let (metadata_kept, commits) = load(&[
+            metadata("first", 0, false), commit("a"), metadata("first", 1, true),
+            metadata("second", 0, false), commit("b"), metadata("second", 1, true),
+        ]);
+        assert_eq!(metadata_kept, Some(("first".to_string(), 1)));
+        assert_eq!(commits, vec!["a", "b"]);
+
+        // The first run was interrupted, its start line is kept
+        let (metadata_kept, _) = load(&[metadata("first", 0, false), commit("a"), metadata("second", 0, false), metadata("second", 1, true)]);
+        assert_eq!(metadata_kept, Some(("first".to_string(), 0)));
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -1,26 +1,236 @@
-// Struct for the following JSON schema:
+// Structs of the output files. The JSON output has the metadata of the run and the data, a
+// list of commits, of snapshots or a report:
 // {
-//     "commit": "string",
-//     "date": "string",
-//     "files": [string]
+//     "metadata": {"schema_version": 2, "tool_version": "string", "repository": {...},
+//                  "configuration": {...}, "start_time": "string", "end_time": "string",
+//                  "counts": {...}},
+//     "data": [{"commit": "string", "date": "string", "size": 0, "test_files": [...], ...}]
 // }
+// The JSON Lines output has one commit per line, between a metadata line written when a run
+// starts and one written when it finishes. The CSV outputs have the metadata in a sidecar
+// <output>.metadata.json file, and the Parquet output in the key-value metadata of the file.
 
-use std::io::{BufWriter};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
 use chrono::NaiveDateTime;
 use serde::{Deserialize, Serialize};
 use color_eyre::eyre::Result;
+use crate::expression_interpreter::Explanation;
+use crate::decoding::SkippedFiles;
+use crate::test_cases::TestChanges;
 
+/// Version of the layout of the output, increased when it changes incompatibly. Version 2
+/// moved the file details into the entries of the files.
+pub const SCHEMA_VERSION: u32 = 2;
+
+/// Top level of the JSON output: how the results were produced, and the results
+#[derive(Deserialize, Debug)]
+pub struct Output<T> {
+    pub metadata: RunMetadata,
+    pub data: Vec<T>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RunMetadata {
+    pub schema_version: u32,
+    pub tool_version: String,
+    pub repository: RepositoryMetadata,
+    /// Every argument of the run, including the defaults
+    pub configuration: serde_json::Value,
+    pub start_time: NativeDateTimeWrapper,
+    pub end_time: NativeDateTimeWrapper,
+    pub counts: RunCounts,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RepositoryMetadata {
+    /// Absolute path of the repository
+    pub path: String,
+    pub branch: String,
+    /// Commit the branch pointed to
+    pub head: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct RunCounts {
+    /// Commits walked in the analysed range
+    pub commits: usize,
+    /// Entries of the data
+    pub results: usize,
+    pub skipped_files: SkippedFiles,
+}
+
+/// Metadata line of the JSON Lines output. Each run appending to the file writes one before
+/// its commits, with the metadata known at the start of the run, and a finished one after
+/// them, with the end time and the counts.
 #[derive(Serialize, Deserialize, Debug)]
+pub struct MetadataLine {
+    pub metadata: RunMetadata,
+    #[serde(default)]
+    pub finished: bool,
+}
+
+impl RunMetadata {
+    /// Records the end of the run and the number of results
+    pub fn finish(&mut self, results: usize) {
+        self.end_time = NativeDateTimeWrapper(chrono::Utc::now().naive_utc());
+        self.counts.results = results;
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(test, derive(Default))]
 pub struct CommitData {
     pub commit: String,
     pub date: NativeDateTimeWrapper,
+    /// Author of the commit, after the identities are merged by the mailmap
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub author: Option<String>,
+    /// Committer of the commit, after the identities are merged by the mailmap
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub committer: Option<String>,
     pub size: usize,
-    pub test_files: Vec<String>,
+    pub test_files: Vec<FileEntry>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub non_test_files: Option<Vec<FileEntry>>,
+    /// Test files changed by the commit outside the match target, such as the deleted files
+    /// with --match-target blob, only listed for their test changes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tracked_test_files: Option<Vec<FileEntry>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub explanations: Option<Vec<FileExplanation>>,
+    /// Total number of test cases in the test files
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub non_test_files: Option<Vec<String>>,
+    pub test_cases: Option<usize>,
+    /// Files matched by each classifier
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub labels: Option<BTreeMap<String, Vec<String>>>,
+}
+
+/// A file of a commit, with the properties detected in it. Written as the bare name when no
+/// property is enabled, so the outputs without them keep a list of names, and as an object
+/// otherwise, even without any property detected, so every entry of a run has the same layout.
+#[derive(Debug, Clone, Default)]
+pub struct FileEntry {
+    pub file: String,
+    pub details: FileDetails,
+    /// Whether the properties are enabled, and the entry is written as an object
+    pub detailed: bool,
+}
+
+/// Detected properties of a file
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct FileDetails {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub framework: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub test_cases: Option<usize>,
+    /// Names of the test cases in the file
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub test_names: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub test_changes: Option<TestChanges>,
+    /// Production files paired with the test file, changed in the same commit
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub production_files: Option<Vec<String>>,
+    /// Production files paired with the test file, recorded anywhere in the analysed history
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub history_production_files: Option<Vec<String>>,
+}
+
+impl FileEntry {
+    /// An entry written as the bare name
+    pub fn new(file: String) -> FileEntry {
+        FileEntry { file, details: FileDetails::default(), detailed: false }
+    }
+
+    /// An entry written as an object with the details
+    pub fn with_details(file: String, details: FileDetails) -> FileEntry {
+        FileEntry { file, details, detailed: true }
+    }
+}
+
+// Layouts of a file entry, the name alone or the name with the details
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum FileEntryLayout<N, D> {
+    Name(N),
+    Details {
+        file: N,
+        #[serde(flatten)]
+        details: D,
+    },
+}
+
+impl Serialize for FileEntry {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        let layout = if !self.detailed {
+            FileEntryLayout::Name(&self.file)
+        } else {
+            FileEntryLayout::Details { file: &self.file, details: &self.details }
+        };
+        layout.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for FileEntry {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        Ok(match FileEntryLayout::<String, FileDetails>::deserialize(deserializer)? {
+            FileEntryLayout::Name(file) => FileEntry::new(file),
+            FileEntryLayout::Details { file, details } => FileEntry::with_details(file, details),
+        })
+    }
+}
+
+#[cfg(test)]
+impl RunMetadata {
+    /// Metadata of a run on the given repository, the other fields are empty
+    pub fn for_repository(path: &str) -> RunMetadata {
+        RunMetadata {
+            schema_version: SCHEMA_VERSION,
+            tool_version: "0.1.0".to_string(),
+            repository: RepositoryMetadata { path: path.to_string(), branch: "master".to_string(), head: "a".to_string() },
+            configuration: serde_json::json!({}),
+            start_time: NativeDateTimeWrapper::default(),
+            end_time: NativeDateTimeWrapper::default(),
+            counts: RunCounts::default(),
+        }
+    }
+}
+
+#[cfg(test)]
+impl CommitData {
+    /// A commit with the given test files, the other fields are empty
+    pub fn with_test_files(commit: &str, test_files: &[&str]) -> CommitData {
+        CommitData {
+            commit: commit.to_string(),
+            test_files: test_files.iter().map(|file| FileEntry::new(file.to_string())).collect(),
+            ..Default::default()
+        }
+    }
+}
+
+/// Evaluation trees of the content and name expressions for a file
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FileExplanation {
+    pub file: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content: Option<Explanation>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<Explanation>,
 }
 
 #[derive(Debug, Clone)]
+#[cfg_attr(test, derive(Default))]
 pub struct NativeDateTimeWrapper(pub NaiveDateTime);
 
 impl Serialize for NativeDateTimeWrapper {
@@ -38,15 +248,172 @@ impl<'de> Deserialize<'de> for NativeDateTimeWrapper {
         D: serde::Deserializer<'de>,
     {
         let s = String::deserialize(deserializer)?;
-        let dt = NaiveDateTime::parse_from_str(&s, "%Y-%m-%d %H:%M:%S UTC").unwrap();
+        let dt = NaiveDateTime::parse_from_str(&s, "%Y-%m-%d %H:%M:%S UTC")
+            .map_err(|e| serde::de::Error::custom(format!("Invalid date {}: {}", s, e)))?;
         Ok(NativeDateTimeWrapper(dt))
     }
 }
 
 
-pub fn write_to_file(commit_data: &[CommitData], file_path: &str) -> Result<()> {
+/// Writes the data, a list of results or a report, with the metadata of the run as pretty JSON
+pub fn write_to_file<T: Serialize + ?Sized>(data: &T, metadata: &RunMetadata, file_path: &str) -> Result<()> {
     let file = std::fs::File::create(file_path)?;
     let mut writer = BufWriter::new(file);
-    serde_json::to_writer_pretty(&mut writer, commit_data)?;
+
+    // Same layout as Output, without cloning the data
+    #[derive(Serialize)]
+    struct OutputRef<'a, T: ?Sized> {
+        metadata: &'a RunMetadata,
+        data: &'a T,
+    }
+    serde_json::to_writer_pretty(&mut writer, &OutputRef { metadata, data })?;
+    Ok(())
+}
+
+/// Writes the metadata of the run as pretty JSON next to an output without room for it, in
+/// <output>.metadata.json
+pub fn write_metadata_file(metadata: &RunMetadata, file_path: &str) -> Result<()> {
+    let file = std::fs::File::create(format!("{}.metadata.json", file_path))?;
+    serde_json::to_writer_pretty(BufWriter::new(file), metadata)?;
     Ok(())
-}
\ No newline at end of file
+}
+
+/// Format of the commits in the output file
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum OutputFormat {
+    /// A single JSON array, written when the analysis finishes
+    Json,
+    /// One JSON object per line, written as the commits are analysed
+    Jsonl,
+    /// One row per commit, file and label, see the tabular module for the columns
+    Csv,
+    /// The rows of the CSV format in a Parquet file
+    Parquet,
+    /// Normalised tables in a SQLite database, see the database module for the tables
+    Sqlite,
+}
+
+/// Writes the commits as JSON Lines as soon as each chunk is analysed. When ordered, the
+/// results are written in the order of their position in the history walk, holding back the
+/// ones that finish before their predecessors.
+pub struct JsonLinesWriter {
+    writer: BufWriter<File>,
+    ordered: bool,
+    next_position: usize,
+    pending: BTreeMap<usize, Option<CommitData>>,
+    written: usize,
+}
+
+impl JsonLinesWriter {
+    /// Opens the output, and writes the metadata line of the run if it is given
+    pub fn create(file_path: &str, append: bool, ordered: bool, metadata: Option<&RunMetadata>) -> Result<JsonLinesWriter> {
+        let file = std::fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .append(append)
+            .truncate(!append)
+            .open(file_path)?;
+
+        let mut writer = BufWriter::new(file);
+        if let Some(metadata) = metadata {
+            serde_json::to_writer(&mut writer, &MetadataLine { metadata: metadata.clone(), finished: false })?;
+            writer.write_all(b"\n")?;
+        }
+
+        Ok(JsonLinesWriter {
+            writer,
+            ordered,
+            next_position: 0,
+            pending: BTreeMap::new(),
+            written: 0,
+        })
+    }
+
+    /// Writes the results of a chunk, given with the position of each commit. Every position
+    /// must be given once, with None for the commits without relevant data.
+    pub fn write_chunk(&mut self, results: Vec<(usize, Option<CommitData>)>) -> Result<()> {
+        if self.ordered {
+            self.pending.extend(results);
+            while let Some(commit_data) = self.pending.remove(&self.next_position) {
+                if let Some(commit_data) = commit_data {
+                    self.write_line(&commit_data)?;
+                }
+                self.next_position += 1;
+            }
+        } else {
+            for commit_data in results.into_iter().filter_map(|(_, commit_data)| commit_data) {
+                self.write_line(&commit_data)?;
+            }
+        }
+
+        // Keep what was analysed so far if the run is interrupted
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    fn write_line(&mut self, commit_data: &CommitData) -> Result<()> {
+        serde_json::to_writer(&mut self.writer, commit_data)?;
+        self.writer.write_all(b"\n")?;
+        self.written += 1;
+        Ok(())
+    }
+
+    /// Writes the finished metadata line of the run if it is given, with the number of commits
+    /// written, flushes the output and returns the number of commits written
+    pub fn finish(mut self, metadata: Option<&mut RunMetadata>) -> Result<usize> {
+        if let Some(metadata) = metadata {
+            metadata.finish(self.written);
+            serde_json::to_writer(&mut self.writer, &MetadataLine { metadata: metadata.clone(), finished: true })?;
+            self.writer.write_all(b"\n")?;
+        }
+        self.writer.flush()?;
+        Ok(self.written)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn commit_data(commit: &str) -> Option<CommitData> {
+        Some(CommitData::with_test_files(commit, &[&format!("{}Test.java", commit)]))
+    }
+
+    #[test]
+    fn test_file_entry_layout() {
+        let mut commit_data = commit_data("a").unwrap();
+        commit_data.test_files.push(FileEntry::with_details(
+            "bTest.java".to_string(),
+            FileDetails { language: Some("Java".to_string()), test_cases: Some(2), ..Default::default() },
+        ));
+        commit_data.test_files.push(FileEntry::with_details("cTest.kt".to_string(), FileDetails::default()));
+
+        let json = serde_json::to_string(&commit_data.test_files).unwrap();
+        assert_eq!(json, r#"["aTest.java",{"file":"bTest.java","language":"Java","test_cases":2},{"file":"cTest.kt"}]"#);
+
+        let entries: Vec<FileEntry> = serde_json::from_str(&json).unwrap();
+        assert_eq!(entries[0].file, "aTest.java");
+        assert!(!entries[0].detailed);
+        assert_eq!((entries[1].file.as_str(), entries[1].details.test_cases), ("bTest.java", Some(2)));
+        assert!(entries[2].detailed);
+    }
+
+    #[test]
+    fn test_ordered_json_lines() {
+        let path = std::env::temp_dir().join(format!("ordered_{}.jsonl", std::process::id()));
+        let path = path.to_str().unwrap();
+
+        let mut writer = JsonLinesWriter::create(path, false, true, None).unwrap();
+        writer.write_chunk(vec![(2, commit_data("c")), (3, None)]).unwrap();
+        writer.write_chunk(vec![(0, commit_data("a")), (1, commit_data("b"))]).unwrap();
+        assert_eq!(writer.finish(None).unwrap(), 3);
+
+        let commits: Vec<String> = std::fs::read_to_string(path).unwrap().lines()
+            .map(|line| serde_json::from_str::<CommitData>(line).unwrap().commit)
+            .collect();
+        assert_eq!(commits, vec!["a", "b", "c"]);
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
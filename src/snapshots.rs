@@ -0,0 +1,71 @@
+// Test inventory of a tree: every file of a tag or commit is classified, instead of the files
+// changed by each commit. Taken at several tags, the snapshots give the size of the test suite
+// over the releases.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use serde::{Deserialize, Serialize};
+use crate::serialization::{FileEntry, NativeDateTimeWrapper};
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Snapshot {
+    /// Revision as given on the command line
+    pub revision: String,
+    pub commit: String,
+    pub date: NativeDateTimeWrapper,
+    /// Files with one of the extensions that could be classified
+    pub files: usize,
+    pub test_files: Vec<FileEntry>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub non_test_files: Option<Vec<FileEntry>>,
+    /// Total number of test cases in the test files
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub test_cases: Option<usize>,
+    /// Files matched by each classifier
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub labels: Option<BTreeMap<String, Vec<String>>>,
+}
+
+/// Formats the size of the test suite at each snapshot as a table with aligned columns
+pub fn format_table(snapshots: &[Snapshot]) -> String {
+    let width = snapshots.iter().map(|snapshot| snapshot.revision.len()).max().unwrap_or(0).max("revision".len());
+
+    let mut table = String::new();
+    writeln!(table, "{:<width$}  {:<10}  {:>7}  {:>10}  {:>10}", "revision", "date", "files", "test files", "test cases").unwrap();
+    for snapshot in snapshots {
+        let test_cases = snapshot.test_cases.map(|test_cases| test_cases.to_string()).unwrap_or_else(|| "-".to_string());
+        writeln!(
+            table, "{:<width$}  {:<10}  {:>7}  {:>10}  {:>10}",
+            snapshot.revision, snapshot.date.0.date(), snapshot.files, snapshot.test_files.len(), test_cases,
+        ).unwrap();
+    }
+
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    #[test]
+    fn test_format_table() {
+        let snapshot = |revision: &str, test_files: usize, test_cases: Option<usize>| Snapshot {
+            revision: revision.to_string(),
+            commit: "abc".to_string(),
+            date: NativeDateTimeWrapper(NaiveDate::from_ymd_opt(2023, 1, 1).unwrap().and_hms_opt(0, 0, 0).unwrap()),
+            files: 10,
+            test_files: vec![FileEntry::new("FooTest.java".to_string()); test_files],
+            non_test_files: None,
+            test_cases,
+            labels: None,
+        };
+
+        let table = format_table(&[snapshot("v1.0", 2, None), snapshot("release-2.0", 5, Some(12))]);
+        let lines: Vec<&str> = table.lines().collect();
+        assert!(lines[0].starts_with("revision     date"));
+        assert!(lines[1].starts_with("v1.0         2023-01-01"));
+        assert!(lines[1].ends_with("2           -"));
+        assert!(lines[2].ends_with("5          12"));
+    }
+}
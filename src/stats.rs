@@ -0,0 +1,224 @@
+// Statistics of the matched commits: the commits are grouped by period, by release or by
+// author, and the test and non-test files they add are counted for each group.
+
+use std::collections::HashMap;
+use std::fmt::Write;
+use color_eyre::eyre::Result;
+use git2::{Oid, Repository};
+use serde::Serialize;
+use crate::serialization::{write_metadata_file, CommitData, RunMetadata};
+
+// Group of the commits after the last release
+const UNRELEASED: &str = "unreleased";
+
+// Group of the commits without an author, in the outputs written before the authors were
+// recorded
+const UNKNOWN_AUTHOR: &str = "unknown";
+
+/// How the commits are grouped in the statistics
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum StatsGroup {
+    /// By the period of the commit date
+    Period,
+    /// By the first release tag containing the commit
+    Release,
+    /// By the author of the commit, with the identities merged by the mailmap
+    Author,
+}
+
+/// Counts of a group of commits
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct StatsRow {
+    /// Period label, release tag or author
+    pub group: String,
+    pub commits: usize,
+    pub commits_with_tests: usize,
+    pub test_files: usize,
+    pub non_test_files: usize,
+    /// Test files per non-test file, none without non-test files
+    pub test_ratio: Option<f64>,
+}
+
+/// Counts the files of the commits in each group, in order of the first commit of each
+/// group. The commits must be sorted by date.
+pub fn stats<F>(commits: &[CommitData], group: F) -> Vec<StatsRow>
+where
+    F: Fn(&CommitData) -> String,
+{
+    let mut rows: Vec<StatsRow> = Vec::new();
+    let mut row_index: HashMap<String, usize> = HashMap::new();
+
+    for commit_data in commits {
+        let group = group(commit_data);
+        let idx = *row_index.entry(group.clone()).or_insert_with(|| {
+            rows.push(StatsRow {
+                group,
+                commits: 0,
+                commits_with_tests: 0,
+                test_files: 0,
+                non_test_files: 0,
+                test_ratio: None,
+            });
+            rows.len() - 1
+        });
+
+        let row = &mut rows[idx];
+        row.commits += 1;
+        if !commit_data.test_files.is_empty() {
+            row.commits_with_tests += 1;
+        }
+        row.test_files += commit_data.test_files.len();
+        row.non_test_files += commit_data.non_test_files.as_ref().map_or(0, |files| files.len());
+    }
+
+    for row in &mut rows {
+        if row.non_test_files > 0 {
+            row.test_ratio = Some(row.test_files as f64 / row.non_test_files as f64);
+        }
+    }
+
+    rows
+}
+
+/// Maps every commit reachable from a tag to the first tag, by date, that contains it
+pub fn release_tags(repo: &Repository) -> Result<HashMap<String, String>> {
+    let mut tags: Vec<(String, Oid, i64)> = Vec::new();
+    for name in repo.tag_names(None)?.iter().flatten() {
+        // The tags of trees and blobs are not releases
+        let Ok(commit) = repo.revparse_single(&format!("refs/tags/{}", name))?.peel_to_commit() else {
+            continue;
+        };
+        tags.push((name.to_string(), commit.id(), commit.time().seconds()));
+    }
+    tags.sort_by_key(|(_, _, time)| *time);
+
+    // Each tag gets the commits not reachable from the previous tags. Only the previous tag is
+    // hidden, the commits of older tags walked again keep their first tag
+    let mut releases = HashMap::new();
+    for (idx, (name, commit, _)) in tags.iter().enumerate() {
+        let mut revwalk = repo.revwalk()?;
+        revwalk.push(*commit)?;
+        if let Some((_, previous, _)) = idx.checked_sub(1).map(|previous| &tags[previous]) {
+            revwalk.hide(*previous)?;
+        }
+
+        for oid in revwalk {
+            releases.entry(oid?.to_string()).or_insert_with(|| name.clone());
+        }
+    }
+
+    Ok(releases)
+}
+
+/// Group of a commit by release, for the commits after the last release too
+pub fn release_of(releases: &HashMap<String, String>, commit_data: &CommitData) -> String {
+    releases.get(&commit_data.commit).cloned().unwrap_or_else(|| UNRELEASED.to_string())
+}
+
+/// Group of a commit by author, unknown for the commits without an author
+pub fn author_of(commit_data: &CommitData) -> String {
+    commit_data.author.clone().unwrap_or_else(|| UNKNOWN_AUTHOR.to_string())
+}
+
+/// Formats the rows as a table with aligned columns
+pub fn format_table(rows: &[StatsRow]) -> String {
+    let width = rows.iter().map(|row| row.group.len()).max().unwrap_or(0).max("group".len());
+
+    let mut table = String::new();
+    writeln!(table, "{:<width$}  {:>7}  {:>18}  {:>10}  {:>14}  {:>10}", "group", "commits", "commits with tests", "test files", "non-test files", "test ratio").unwrap();
+    for row in rows {
+        let ratio = row.test_ratio.map(|ratio| format!("{:.2}", ratio)).unwrap_or_else(|| "-".to_string());
+        writeln!(table, "{:<width$}  {:>7}  {:>18}  {:>10}  {:>14}  {:>10}", row.group, row.commits, row.commits_with_tests, row.test_files, row.non_test_files, ratio).unwrap();
+    }
+
+    table
+}
+
+pub fn write_stats_csv(rows: &[StatsRow], metadata: &RunMetadata, file_path: &str) -> Result<()> {
+    let mut writer = csv::Writer::from_path(file_path)?;
+    for row in rows {
+        writer.serialize(row)?;
+    }
+    writer.flush()?;
+    write_metadata_file(metadata, file_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDateTime;
+    use crate::periods::Period;
+    use crate::serialization::{FileEntry, NativeDateTimeWrapper};
+
+    fn commit_data(date: &str, author: Option<&str>, test_files: usize, non_test_files: usize) -> CommitData {
+        CommitData {
+            date: NativeDateTimeWrapper(NaiveDateTime::parse_from_str(date, "%Y-%m-%d %H:%M:%S").unwrap()),
+            author: author.map(String::from),
+            non_test_files: Some(vec![FileEntry::new("Foo.java".to_string()); non_test_files]),
+            ..CommitData::with_test_files(date, &vec!["FooTest.java"; test_files])
+        }
+    }
+
+    #[test]
+    fn test_stats_by_period() {
+        let commits = vec![
+            commit_data("2023-01-05 10:00:00", None, 1, 2),
+            commit_data("2023-01-20 10:00:00", None, 0, 1),
+            commit_data("2023-02-01 10:00:00", None, 2, 0),
+        ];
+
+        let rows = stats(&commits, |commit_data| Period::Month.label(&commit_data.date.0));
+        assert_eq!(rows, vec![
+            StatsRow { group: "2023-01".to_string(), commits: 2, commits_with_tests: 1, test_files: 1, non_test_files: 3, test_ratio: Some(1.0 / 3.0) },
+            StatsRow { group: "2023-02".to_string(), commits: 1, commits_with_tests: 1, test_files: 2, non_test_files: 0, test_ratio: None },
+        ]);
+
+        let table = format_table(&rows);
+        assert!(table.lines().nth(1).unwrap().ends_with("0.33"));
+    }
+
+    #[test]
+    fn test_stats_by_author() {
+        let jane = Some("Jane <jane@example.com>");
+        let commits = vec![
+            commit_data("2023-01-05 10:00:00", jane, 1, 2),
+            commit_data("2023-01-20 10:00:00", None, 0, 1),
+            commit_data("2023-02-01 10:00:00", jane, 2, 2),
+        ];
+
+        let rows = stats(&commits, author_of);
+        assert_eq!(rows.len(), 2);
+        assert_eq!((rows[0].group.as_str(), rows[0].commits, rows[0].test_files, rows[0].test_ratio), ("Jane <jane@example.com>", 2, 3, Some(0.75)));
+        assert_eq!((rows[1].group.as_str(), rows[1].commits_with_tests), (UNKNOWN_AUTHOR, 0));
+    }
+
+    #[test]
+    fn test_release_tags() {
+        let path = std::env::temp_dir().join(format!("stats_release_tags_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+        let repo = Repository::init(&path).unwrap();
+
+        // Three commits a second apart, the first tagged v1 and the last v2
+        let tree = repo.find_tree(repo.treebuilder(None).unwrap().write().unwrap()).unwrap();
+        let mut commits = Vec::new();
+        for seconds in 1..=3 {
+            let signature = git2::Signature::new("Jane", "jane@example.com", &git2::Time::new(seconds, 0)).unwrap();
+            let parents: Vec<git2::Commit> = commits.last().map(|oid| repo.find_commit(*oid).unwrap()).into_iter().collect();
+            let parents: Vec<&git2::Commit> = parents.iter().collect();
+            commits.push(repo.commit(None, &signature, &signature, "commit", &tree, &parents).unwrap());
+        }
+        repo.tag_lightweight("v1", &repo.find_object(commits[0], None).unwrap(), false).unwrap();
+        repo.tag_lightweight("v2", &repo.find_object(commits[2], None).unwrap(), false).unwrap();
+
+        // A tag of a blob is skipped
+        let blob = repo.blob(b"key").unwrap();
+        repo.tag_lightweight("key", &repo.find_object(blob, None).unwrap(), false).unwrap();
+
+        let releases = release_tags(&repo).unwrap();
+        let release = |idx: usize| releases.get(&commits[idx].to_string()).map(String::as_str);
+        assert_eq!((release(0), release(1), release(2)), (Some("v1"), Some("v2"), Some("v2")));
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+}
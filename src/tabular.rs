@@ -0,0 +1,253 @@
+// Tabular output of the commits, flattened to one row per commit, file and label, for loading
+// into dataframes and databases. The columns are a stable schema, new columns are only added
+// at the end:
+//
+// commit      string     id of the commit
+// date        string     commit date, YYYY-MM-DD HH:MM:SS UTC
+// size        integer    lines changed by the commit
+// file        string     name or path of the file
+// is_test     boolean    whether the file matched the expression
+// label       string     label of a classifier that matched the file, empty if none did.
+//                        A file with several labels has a row for each of them
+// language    string     detected language, with --detect-frameworks
+// framework   string     detected test framework, with --detect-frameworks
+// test_cases  integer    test cases declared in the file, with --count-tests
+// author      string     author of the commit, Name <email> resolved by the mailmap
+// committer   string     committer of the commit, Name <email> resolved by the mailmap
+//
+// The metadata of the run is written to <output>.metadata.json for CSV, and to the key-value
+// metadata of the file for Parquet.
+
+use std::sync::Arc;
+use color_eyre::eyre::Result;
+use parquet::basic::Compression;
+use parquet::data_type::{BoolType, ByteArray, ByteArrayType, Int64Type};
+use parquet::file::properties::WriterProperties;
+use parquet::file::writer::SerializedFileWriter;
+use parquet::format::KeyValue;
+use parquet::schema::parser::parse_message_type;
+use serde::Serialize;
+use crate::serialization::{write_metadata_file, CommitData, FileDetails, RunMetadata};
+
+// Key of the metadata of the run in the key-value metadata of the Parquet file
+const PARQUET_METADATA_KEY: &str = "metadata";
+
+const PARQUET_SCHEMA: &str = "
+    message commit_files {
+        REQUIRED BYTE_ARRAY commit (UTF8);
+        REQUIRED BYTE_ARRAY date (UTF8);
+        REQUIRED INT64 size;
+        REQUIRED BYTE_ARRAY file (UTF8);
+        REQUIRED BOOLEAN is_test;
+        OPTIONAL BYTE_ARRAY label (UTF8);
+        OPTIONAL BYTE_ARRAY language (UTF8);
+        OPTIONAL BYTE_ARRAY framework (UTF8);
+        OPTIONAL INT64 test_cases;
+        OPTIONAL BYTE_ARRAY author (UTF8);
+        OPTIONAL BYTE_ARRAY committer (UTF8);
+    }
+";
+
+/// A row of the tabular formats, with the columns in schema order
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct Row {
+    pub commit: String,
+    pub date: String,
+    pub size: i64,
+    pub file: String,
+    pub is_test: bool,
+    pub label: Option<String>,
+    pub language: Option<String>,
+    pub framework: Option<String>,
+    pub test_cases: Option<i64>,
+    pub author: Option<String>,
+    pub committer: Option<String>,
+}
+
+/// Flattens the commits into rows, in the order of the commits and of their files
+pub fn rows(commits: &[CommitData]) -> Vec<Row> {
+    let mut rows = Vec::new();
+
+    for commit_data in commits {
+        // The labelled files are not listed elsewhere if they are not tests
+        let mut files: Vec<(&String, bool, Option<&FileDetails>)> = commit_data.test_files.iter()
+            .map(|entry| (&entry.file, true, Some(&entry.details)))
+            .chain(commit_data.non_test_files.iter().flatten().map(|entry| (&entry.file, false, Some(&entry.details))))
+            .collect();
+        for labelled in commit_data.labels.iter().flat_map(|labels| labels.values()).flatten() {
+            if !files.iter().any(|(file, _, _)| *file == labelled) {
+                files.push((labelled, false, None));
+            }
+        }
+
+        for (file, is_test, details) in files {
+            let mut labels: Vec<Option<String>> = commit_data.labels.iter()
+                .flat_map(|labels| labels.iter())
+                .filter(|(_, labelled)| labelled.contains(file))
+                .map(|(label, _)| Some(label.clone()))
+                .collect();
+            if labels.is_empty() {
+                labels.push(None);
+            }
+
+            for label in labels {
+                rows.push(Row {
+                    commit: commit_data.commit.clone(),
+                    date: commit_data.date.0.format("%Y-%m-%d %H:%M:%S UTC").to_string(),
+                    size: commit_data.size as i64,
+                    file: file.clone(),
+                    is_test,
+                    label,
+                    language: details.and_then(|details| details.language.clone()),
+                    framework: details.and_then(|details| details.framework.clone()),
+                    test_cases: details.and_then(|details| details.test_cases).map(|test_cases| test_cases as i64),
+                    author: commit_data.author.clone(),
+                    committer: commit_data.committer.clone(),
+                });
+            }
+        }
+    }
+
+    rows
+}
+
+/// Writes the rows as CSV, with the metadata of the run in <output>.metadata.json
+pub fn write_csv(rows: &[Row], metadata: &RunMetadata, file_path: &str) -> Result<()> {
+    let mut writer = csv::Writer::from_path(file_path)?;
+    for row in rows {
+        writer.serialize(row)?;
+    }
+    writer.flush()?;
+    write_metadata_file(metadata, file_path)
+}
+
+/// Writes the rows as Parquet, with the metadata of the run as JSON under the metadata key of
+/// the key-value metadata of the file
+pub fn write_parquet(rows: &[Row], metadata: &RunMetadata, file_path: &str) -> Result<()> {
+    let schema = Arc::new(parse_message_type(PARQUET_SCHEMA)?);
+    let key_value_metadata = KeyValue::new(PARQUET_METADATA_KEY.to_string(), serde_json::to_string(metadata)?);
+    let properties = Arc::new(WriterProperties::builder()
+        .set_compression(Compression::UNCOMPRESSED)
+        .set_key_value_metadata(Some(vec![key_value_metadata]))
+        .build());
+    let file = std::fs::File::create(file_path)?;
+    let mut writer = SerializedFileWriter::new(file, schema, properties)?;
+
+    let mut row_group = writer.next_row_group()?;
+    let mut column_index = 0;
+    while let Some(mut column) = row_group.next_column()? {
+        match column_index {
+            0 => write_strings(column.typed::<ByteArrayType>(), rows.iter().map(|row| Some(row.commit.as_str())), false)?,
+            1 => write_strings(column.typed::<ByteArrayType>(), rows.iter().map(|row| Some(row.date.as_str())), false)?,
+            2 => write_integers(column.typed::<Int64Type>(), rows.iter().map(|row| Some(row.size)), false)?,
+            3 => write_strings(column.typed::<ByteArrayType>(), rows.iter().map(|row| Some(row.file.as_str())), false)?,
+            4 => {
+                let values: Vec<bool> = rows.iter().map(|row| row.is_test).collect();
+                column.typed::<BoolType>().write_batch(&values, None, None)?;
+            }
+            5 => write_strings(column.typed::<ByteArrayType>(), rows.iter().map(|row| row.label.as_deref()), true)?,
+            6 => write_strings(column.typed::<ByteArrayType>(), rows.iter().map(|row| row.language.as_deref()), true)?,
+            7 => write_strings(column.typed::<ByteArrayType>(), rows.iter().map(|row| row.framework.as_deref()), true)?,
+            8 => write_integers(column.typed::<Int64Type>(), rows.iter().map(|row| row.test_cases), true)?,
+            9 => write_strings(column.typed::<ByteArrayType>(), rows.iter().map(|row| row.author.as_deref()), true)?,
+            _ => write_strings(column.typed::<ByteArrayType>(), rows.iter().map(|row| row.committer.as_deref()), true)?,
+        }
+        column.close()?;
+        column_index += 1;
+    }
+
+    row_group.close()?;
+    writer.close()?;
+    Ok(())
+}
+
+// Writes the values of a string column. The definition levels mark the missing values of an
+// optional column.
+fn write_strings<'a>(
+    column: &mut parquet::column::writer::ColumnWriterImpl<'_, ByteArrayType>,
+    values: impl Iterator<Item = Option<&'a str>>,
+    optional: bool,
+) -> Result<()> {
+    let values: Vec<Option<&str>> = values.collect();
+    let present: Vec<ByteArray> = values.iter().flatten().map(|value| ByteArray::from(*value)).collect();
+    let definition_levels: Vec<i16> = values.iter().map(|value| value.is_some() as i16).collect();
+
+    column.write_batch(&present, optional.then_some(definition_levels.as_slice()), None)?;
+    Ok(())
+}
+
+fn write_integers(
+    column: &mut parquet::column::writer::ColumnWriterImpl<'_, Int64Type>,
+    values: impl Iterator<Item = Option<i64>>,
+    optional: bool,
+) -> Result<()> {
+    let values: Vec<Option<i64>> = values.collect();
+    let present: Vec<i64> = values.iter().flatten().copied().collect();
+    let definition_levels: Vec<i16> = values.iter().map(|value| value.is_some() as i16).collect();
+
+    column.write_batch(&present, optional.then_some(definition_levels.as_slice()), None)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_rows() {
+        let mut labels = BTreeMap::new();
+        labels.insert("mock".to_string(), vec!["FooTest.java".to_string(), "Mocks.java".to_string()]);
+        labels.insert("slow".to_string(), vec!["FooTest.java".to_string()]);
+        let commit_data = CommitData { labels: Some(labels), ..CommitData::with_test_files("a", &["FooTest.java", "BarTest.java"]) };
+
+        let rows: Vec<(String, bool, Option<String>)> = rows(&[commit_data]).into_iter()
+            .map(|row| (row.file, row.is_test, row.label))
+            .collect();
+        assert_eq!(rows, vec![
+            ("FooTest.java".to_string(), true, Some("mock".to_string())),
+            ("FooTest.java".to_string(), true, Some("slow".to_string())),
+            ("BarTest.java".to_string(), true, None),
+            ("Mocks.java".to_string(), false, Some("mock".to_string())),
+        ]);
+    }
+
+    #[test]
+    fn test_write_parquet() {
+        use parquet::file::reader::{FileReader, SerializedFileReader};
+
+        let path = std::env::temp_dir().join(format!("rows_{}.parquet", std::process::id()));
+        let path = path.to_str().unwrap();
+        let row = Row {
+            commit: "a".to_string(),
+            date: "2023-05-14 10:00:00 UTC".to_string(),
+            size: 3,
+            file: "FooTest.java".to_string(),
+            is_test: true,
+            label: None,
+            language: Some("Java".to_string()),
+            framework: None,
+            test_cases: Some(2),
+            author: Some("Jane <jane@example.com>".to_string()),
+            committer: None,
+        };
+        let metadata = RunMetadata::for_repository("/repo");
+        write_parquet(&[row.clone(), Row { label: Some("mock".to_string()), ..row }], &metadata, path).unwrap();
+
+        let reader = SerializedFileReader::new(std::fs::File::open(path).unwrap()).unwrap();
+        assert_eq!(reader.metadata().file_metadata().num_rows(), 2);
+        let key_value_metadata = reader.metadata().file_metadata().key_value_metadata().unwrap();
+        let written: RunMetadata = serde_json::from_str(key_value_metadata[0].value.as_deref().unwrap()).unwrap();
+        assert_eq!((key_value_metadata[0].key.as_str(), written.repository.path.as_str()), (PARQUET_METADATA_KEY, "/repo"));
+        let labels: Vec<String> = reader.get_row_iter(None).unwrap()
+            .map(|row| row.unwrap().get_column_iter().nth(5).unwrap().1.to_string())
+            .collect();
+        assert_eq!(labels, vec!["null", "\"mock\""]);
+        let authors: Vec<String> = reader.get_row_iter(None).unwrap()
+            .map(|row| row.unwrap().get_column_iter().nth(9).unwrap().1.to_string())
+            .collect();
+        assert_eq!(authors, vec!["\"Jane <jane@example.com>\""; 2]);
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
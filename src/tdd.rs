@@ -0,0 +1,172 @@
+// Test-first detection: for each pair of test and production files, whether the test was
+// added before, together with or after the production code.
+
+use std::collections::{BTreeMap, HashSet};
+use serde::{Deserialize, Serialize};
+use crate::coevolution::{collect_test_imports, CommitChanges};
+use crate::pairing::is_pair;
+use crate::periods::Period;
+use crate::serialization::NativeDateTimeWrapper;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TestOrder {
+    TestFirst,
+    SameCommit,
+    TestAfter,
+}
+
+/// Classification of a pair of test and production files
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PairOrder {
+    pub test_file: String,
+    pub production_file: String,
+    /// First commit touching the test file
+    pub test_commit: String,
+    /// First commit touching the production file
+    pub production_commit: String,
+    pub order: TestOrder,
+    /// Author of the earliest of the two commits
+    pub author: String,
+    /// Date of the earliest of the two commits
+    pub date: NativeDateTimeWrapper,
+}
+
+/// Number of pairs of each order and their ratios
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct OrderRatios {
+    pub pairs: usize,
+    pub test_first: usize,
+    pub same_commit: usize,
+    pub test_after: usize,
+    pub test_first_ratio: f64,
+    pub same_commit_ratio: f64,
+    pub test_after_ratio: f64,
+}
+
+impl OrderRatios {
+    fn add(&mut self, order: TestOrder) {
+        self.pairs += 1;
+        match order {
+            TestOrder::TestFirst => self.test_first += 1,
+            TestOrder::SameCommit => self.same_commit += 1,
+            TestOrder::TestAfter => self.test_after += 1,
+        }
+
+        let pairs = self.pairs as f64;
+        self.test_first_ratio = self.test_first as f64 / pairs;
+        self.same_commit_ratio = self.same_commit as f64 / pairs;
+        self.test_after_ratio = self.test_after as f64 / pairs;
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TddReport {
+    pub total: OrderRatios,
+    pub by_author: BTreeMap<String, OrderRatios>,
+    pub by_period: BTreeMap<String, OrderRatios>,
+    pub pairs: Vec<PairOrder>,
+}
+
+/// Classifies every pair of test and production files by the order of the first commits
+/// touching each side. The changes must be sorted by position.
+pub fn tdd_report(changes: &[CommitChanges], period: Period) -> TddReport {
+    // First commit touching each file, a file is classified by its first change
+    let mut first_test_commit: Vec<(&str, usize)> = Vec::new();
+    let mut first_production_commit: Vec<(&str, usize)> = Vec::new();
+    let mut seen: HashSet<&str> = HashSet::new();
+    for (idx, commit) in changes.iter().enumerate() {
+        for test_file in &commit.test_files {
+            if seen.insert(test_file) {
+                first_test_commit.push((test_file, idx));
+            }
+        }
+        for production_file in &commit.production_files {
+            if seen.insert(production_file) {
+                first_production_commit.push((production_file, idx));
+            }
+        }
+    }
+
+    let test_imports = collect_test_imports(changes);
+
+    let mut report = TddReport {
+        total: OrderRatios::default(),
+        by_author: BTreeMap::new(),
+        by_period: BTreeMap::new(),
+        pairs: Vec::new(),
+    };
+
+    for (test_file, test_idx) in &first_test_commit {
+        for (production_file, production_idx) in &first_production_commit {
+            if !is_pair(test_file, production_file, &test_imports[test_file]) {
+                continue;
+            }
+
+            let order = match test_idx.cmp(production_idx) {
+                std::cmp::Ordering::Less => TestOrder::TestFirst,
+                std::cmp::Ordering::Equal => TestOrder::SameCommit,
+                std::cmp::Ordering::Greater => TestOrder::TestAfter,
+            };
+
+            let first = &changes[*test_idx.min(production_idx)];
+            report.total.add(order);
+            report.by_author.entry(first.author.clone()).or_default().add(order);
+            report.by_period.entry(period.label(&first.date)).or_default().add(order);
+
+            report.pairs.push(PairOrder {
+                test_file: test_file.to_string(),
+                production_file: production_file.to_string(),
+                test_commit: changes[*test_idx].commit.clone(),
+                production_commit: changes[*production_idx].commit.clone(),
+                order,
+                author: first.author.clone(),
+                date: NativeDateTimeWrapper(first.date),
+            });
+        }
+    }
+
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+    use super::*;
+
+    fn changes(commit: &str, author: &str, month: u32, test_files: &[&str], production_files: &[&str]) -> CommitChanges {
+        CommitChanges {
+            commit: commit.to_string(),
+            position: 0,
+            author: author.to_string(),
+            date: NaiveDate::from_ymd_opt(2023, month, 1).unwrap().and_hms_opt(0, 0, 0).unwrap(),
+            test_files: test_files.iter().map(|file| file.to_string()).collect(),
+            production_files: production_files.iter().map(|file| file.to_string()).collect(),
+            test_imports: vec![Vec::new(); test_files.len()],
+        }
+    }
+
+    #[test]
+    fn test_tdd_report() {
+        let history = vec![
+            changes("a", "alice", 1, &["FooTest.java"], &[]),
+            changes("b", "bob", 1, &[], &["Foo.java", "Bar.java"]),
+            changes("c", "bob", 2, &["BarTest.java", "FooTest.java"], &["Baz.java"]),
+            changes("d", "alice", 3, &["BazTest.java"], &["Baz.java", "Qux.java"]),
+        ];
+
+        let report = tdd_report(&history, Period::Month);
+        let orders: Vec<(&str, TestOrder)> = report.pairs.iter().map(|pair| (pair.test_file.as_str(), pair.order)).collect();
+        assert_eq!(orders, vec![
+            ("FooTest.java", TestOrder::TestFirst),
+            ("BarTest.java", TestOrder::TestAfter),
+            ("BazTest.java", TestOrder::TestAfter),
+        ]);
+
+        assert_eq!(report.total.pairs, 3);
+        assert_eq!(report.by_author["alice"].test_first, 1);
+        assert_eq!(report.by_author["bob"].test_after, 2);
+        assert_eq!(report.by_period["2023-01"].pairs, 2);
+        assert_eq!(report.by_period["2023-02"].test_after, 1);
+    }
+}
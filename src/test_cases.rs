@@ -0,0 +1,177 @@
+// Extracts the test cases declared in a file, using the conventions of its language, and
+// compares the test cases of two versions of a file.
+
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+// Patterns of the test case declarations of a language, capturing the test name in the group
+// `name`. The patterns are tried in order and the first one that matches is used, so a
+// fallback convention is only used when the preferred one is absent.
+struct TestCaseRule {
+    language: &'static str,
+    patterns: Vec<Regex>,
+}
+
+impl TestCaseRule {
+    fn new(language: &'static str, patterns: &[&str]) -> TestCaseRule {
+        TestCaseRule {
+            language,
+            patterns: patterns.iter().map(|pattern| Regex::new(pattern).unwrap()).collect(),
+        }
+    }
+}
+
+lazy_static! {
+    static ref TEST_CASE_RULES: Vec<TestCaseRule> = vec![
+        // Annotated methods, or JUnit 3 methods named test*
+        TestCaseRule::new("Java", &[
+            r"@(Test|ParameterizedTest|RepeatedTest|TestFactory|TestTemplate)\b[^;{]*?[\s>\]](?P<name>\w+)\s*\(",
+            r"(?m)^\s*public\s+void\s+(?P<name>test\w*)\s*\(",
+        ]),
+        TestCaseRule::new("Kotlin", &[r"@(Test|ParameterizedTest|RepeatedTest|TestFactory)\b[^{]*?\bfun\s+(?P<name>\w+|`[^`]+`)\s*\("]),
+        TestCaseRule::new("Groovy", &[
+            r"@Test\b[^;{]*?[\s>\]](?P<name>\w+)\s*\(",
+            r#"(?m)^\s*def\s+"(?P<name>[^"]+)"\s*\("#,
+        ]),
+        TestCaseRule::new("Scala", &[r#"(?m)^\s*(test|it|should)\s*\(\s*"(?P<name>[^"]+)""#]),
+        TestCaseRule::new("Python", &[r"(?m)^\s*(async\s+)?def\s+(?P<name>test\w*)\s*\("]),
+        TestCaseRule::new("JavaScript", &[r#"(?m)(^|[^.\w])(it|test)(\.(only|skip|todo))?\s*\(\s*['"`](?P<name>[^'"`]*)['"`]"#]),
+        TestCaseRule::new("TypeScript", &[r#"(?m)(^|[^.\w])(it|test)(\.(only|skip|todo))?\s*\(\s*['"`](?P<name>[^'"`]*)['"`]"#]),
+        TestCaseRule::new("Go", &[r"(?m)^func\s+(?P<name>Test\w*)\s*\("]),
+        TestCaseRule::new("Rust", &[r"#\[(\w+::)?test\]\s*(#\[[^\]]*\]\s*)*(pub\s+)?(async\s+)?fn\s+(?P<name>\w+)"]),
+        TestCaseRule::new("C#", &[r"\[(Test|Fact|Theory|TestMethod)(\(|\])[^{;]*?[\s>\]](?P<name>\w+)\s*\("]),
+        TestCaseRule::new("Ruby", &[
+            r#"(?m)^\s*(it|specify|test)\s+['"](?P<name>[^'"]*)['"]"#,
+            r"(?m)^\s*def\s+(?P<name>test_\w*)",
+        ]),
+        TestCaseRule::new("PHP", &[
+            r"(?m)^\s*(public\s+)?function\s+(?P<name>test\w*)\s*\(",
+            r"@test\b[^{]*?function\s+(?P<name>\w+)\s*\(",
+        ]),
+    ];
+}
+
+/// A test case declared in a file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TestCase {
+    pub name: String,
+    /// Text from the declaration to the next test case, with the whitespace collapsed.
+    /// Used to recognise renamed test cases.
+    body: String,
+}
+
+/// Returns the test cases in the content of a file of the given language, in order.
+/// Returns None if the language has no known test case convention.
+pub fn extract_test_cases(language: &str, content: &str) -> Option<Vec<TestCase>> {
+    let rule = TEST_CASE_RULES.iter().find(|rule| rule.language == language)?;
+
+    let captures = rule.patterns.iter()
+        .map(|pattern| pattern.captures_iter(content).collect::<Vec<_>>())
+        .find(|captures| !captures.is_empty())
+        .unwrap_or_default();
+
+    let mut test_cases = Vec::new();
+    for (i, capture) in captures.iter().enumerate() {
+        let name = capture.name("name").unwrap();
+        let body_end = captures.get(i + 1).map(|next| next.get(0).unwrap().start()).unwrap_or(content.len());
+        let body = content[name.end()..body_end].split_whitespace().collect::<Vec<_>>().join(" ");
+
+        test_cases.push(TestCase {
+            name: name.as_str().to_string(),
+            body,
+        });
+    }
+
+    Some(test_cases)
+}
+
+/// Test cases added, removed and renamed between two versions of a file
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct TestChanges {
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub added: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub removed: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub renamed: Vec<TestRename>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct TestRename {
+    pub from: String,
+    pub to: String,
+}
+
+/// Compares the test cases of the old and new versions of a file. A removed and an added
+/// test case with the same body are reported as a rename.
+pub fn diff_test_cases(old: &[TestCase], new: &[TestCase]) -> TestChanges {
+    let mut removed: Vec<&TestCase> = Vec::new();
+    for test_case in old {
+        if !new.iter().any(|new| new.name == test_case.name) && !removed.iter().any(|removed| removed.name == test_case.name) {
+            removed.push(test_case);
+        }
+    }
+
+    let mut added: Vec<&TestCase> = Vec::new();
+    for test_case in new {
+        if !old.iter().any(|old| old.name == test_case.name) && !added.iter().any(|added| added.name == test_case.name) {
+            added.push(test_case);
+        }
+    }
+
+    let mut changes = TestChanges::default();
+    for test_case in removed {
+        match added.iter().position(|added| added.body == test_case.body) {
+            Some(position) => {
+                let renamed = added.remove(position);
+                changes.renamed.push(TestRename {
+                    from: test_case.name.clone(),
+                    to: renamed.name.clone(),
+                });
+            }
+            None => changes.removed.push(test_case.name.clone()),
+        }
+    }
+    changes.added = added.into_iter().map(|test_case| test_case.name.clone()).collect();
+
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(language: &str, content: &str) -> Vec<String> {
+        extract_test_cases(language, content).unwrap().into_iter().map(|test_case| test_case.name).collect()
+    }
+
+    #[test]
+    fn test_extract_java() {
+        let junit4 = "import org.junit.Test;\nclass FooTest {\n  @Test\n  public void testA() {}\n  @Test(expected = E.class)\n  @DisplayName(\"b\")\n  public void b() {}\n}";
+        assert_eq!(names("Java", junit4), vec!["testA", "b"]);
+
+        let junit3 = "class FooTest extends TestCase {\n  public void testA() {}\n  public void testB() {}\n  public void helper() {}\n}";
+        assert_eq!(names("Java", junit3), vec!["testA", "testB"]);
+    }
+
+    #[test]
+    fn test_extract_other_languages() {
+        assert_eq!(names("Python", "def test_a():\n    pass\n\nasync def test_b():\n    pass\ndef helper(): pass"), vec!["test_a", "test_b"]);
+        assert_eq!(names("JavaScript", "describe('a', () => {\n  it('does b', () => {});\n  test.skip(\"c\", () => {});\n  /x/.test(s);\n});"), vec!["does b", "c"]);
+        assert_eq!(names("Go", "func TestA(t *testing.T) {}\nfunc helper() {}"), vec!["TestA"]);
+        assert_eq!(names("Rust", "#[test]\nfn a() {}\n#[tokio::test]\n#[ignore]\nasync fn b() {}"), vec!["a", "b"]);
+        assert_eq!(extract_test_cases("Lisp", "(deftest a)"), None);
+    }
+
+    #[test]
+    fn test_diff_test_cases() {
+        let old = extract_test_cases("Python", "def test_a():\n    assert f(1)\n\ndef test_b():\n    assert g(2)\n\ndef test_c():\n    pass\n").unwrap();
+        let new = extract_test_cases("Python", "def test_a():\n    assert f(1)\n\ndef test_renamed():\n    assert g(2)\n\ndef test_d():\n    assert h(3)\n").unwrap();
+
+        let changes = diff_test_cases(&old, &new);
+        assert_eq!(changes.added, vec!["test_d"]);
+        assert_eq!(changes.removed, vec!["test_c"]);
+        assert_eq!(changes.renamed, vec![TestRename { from: "test_b".to_string(), to: "test_renamed".to_string() }]);
+    }
+}